@@ -0,0 +1,122 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+// 时钟抽象，所有与时间相关的逻辑都通过它读取时间
+pub trait Clock: Send + Sync {
+    // 单调时间(ms)，用于退避、缓存过期等时间间隔计算
+    fn now_millis(&self) -> u64;
+
+    // 墙上时间(ms since UNIX_EPOCH)，用于消息时间戳
+    fn unix_millis(&self) -> u64;
+}
+
+// 系统时钟
+pub struct SystemClock {
+    origin: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        Self {
+            origin: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u64 {
+        self.origin.elapsed().as_millis() as u64
+    }
+
+    fn unix_millis(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64)
+    }
+}
+
+// 手动推进的时钟，用于测试和模拟；克隆后共享同一时间
+// 单调时间和墙上时间分开记录，回拨墙上时间不会让单调时间倒退
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    monotonic: Arc<AtomicU64>,
+    wall: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    pub fn new(start: u64) -> Self {
+        Self {
+            monotonic: Arc::new(AtomicU64::new(start)),
+            wall: Arc::new(AtomicU64::new(start)),
+        }
+    }
+
+    // 设置墙上时间；单调时间只会前移到该值，不会后退
+    pub fn set(&self, millis: u64) {
+        self.wall.store(millis, Ordering::SeqCst);
+        self.monotonic.fetch_max(millis, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, millis: u64) {
+        self.monotonic.fetch_add(millis, Ordering::SeqCst);
+        self.wall.fetch_add(millis, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_millis(&self) -> u64 {
+        self.monotonic.load(Ordering::SeqCst)
+    }
+
+    fn unix_millis(&self) -> u64 {
+        self.wall.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manual_clock_clones_share_time() {
+        let clock = ManualClock::new(1000);
+        let shared = clock.clone();
+
+        clock.advance(500);
+        assert_eq!(shared.now_millis(), 1500);
+        assert_eq!(shared.unix_millis(), 1500);
+
+        shared.set(2000);
+        assert_eq!(clock.now_millis(), 2000);
+        assert_eq!(clock.unix_millis(), 2000);
+    }
+
+    #[test]
+    fn manual_clock_set_backwards_only_moves_wall_time() {
+        let clock = ManualClock::new(1000);
+
+        clock.set(10);
+        assert_eq!(clock.unix_millis(), 10);
+        assert_eq!(clock.now_millis(), 1000);
+
+        clock.advance(5);
+        assert_eq!(clock.unix_millis(), 15);
+        assert_eq!(clock.now_millis(), 1005);
+    }
+
+    #[test]
+    fn system_clock_is_monotonic() {
+        let clock = SystemClock::new();
+        let first = clock.now_millis();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        assert!(clock.now_millis() >= first + 5);
+        assert!(clock.unix_millis() > 0);
+    }
+}
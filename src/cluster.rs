@@ -0,0 +1,484 @@
+use crate::config::NodeConfig;
+use crate::error::GossipSubError;
+use crate::events::GossipSubEvent;
+use crate::network::NodeHandle;
+use crate::types::GossipSubConfig;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::info;
+
+// random-regular拓扑未指定度数时使用的默认值
+const DEFAULT_DEGREE: usize = 3;
+// 修复随机正则图中自环和重边时最多尝试的边交换次数
+const MAX_REGULAR_SWITCHES: usize = 100_000;
+
+// 集群节点之间的连接拓扑
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Topology {
+    Full,                 // 完全连接
+    Ring,                 // 环形
+    Star,                 // 所有节点连接到第一个节点
+    RandomRegular(usize), // 每个节点随机连接固定数量的节点
+}
+
+impl FromStr for Topology {
+    type Err = GossipSubError;
+
+    // 支持 full、ring、star、random-regular 和 random-regular:<度数>
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, degree) = match s.split_once(':') {
+            Some((name, degree)) => (name, Some(degree)),
+            None => (s, None),
+        };
+        match (name, degree) {
+            ("full", None) => Ok(Topology::Full),
+            ("ring", None) => Ok(Topology::Ring),
+            ("star", None) => Ok(Topology::Star),
+            ("random-regular", None) => Ok(Topology::RandomRegular(DEFAULT_DEGREE)),
+            ("random-regular", Some(degree)) => degree
+                .parse()
+                .map(Topology::RandomRegular)
+                .map_err(|_| GossipSubError::InvalidConfig(format!("无效的度数: {}", degree))),
+            _ => Err(GossipSubError::InvalidConfig(format!("未知拓扑: {}", s))),
+        }
+    }
+}
+
+impl fmt::Display for Topology {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Topology::Full => write!(f, "full"),
+            Topology::Ring => write!(f, "ring"),
+            Topology::Star => write!(f, "star"),
+            Topology::RandomRegular(degree) => write!(f, "random-regular:{}", degree),
+        }
+    }
+}
+
+impl Topology {
+    // 生成n个节点之间的连接 (dialer, listener)，dialer < listener
+    pub fn edges(&self, n: usize, rng: &mut StdRng) -> Result<Vec<(usize, usize)>, GossipSubError> {
+        let edges = match *self {
+            Topology::Full => (0..n)
+                .flat_map(|i| (i + 1..n).map(move |j| (i, j)))
+                .collect(),
+            Topology::Ring if n < 3 => (1..n).map(|j| (0, j)).collect(),
+            Topology::Ring => (0..n)
+                .map(|i| {
+                    let j = (i + 1) % n;
+                    (i.min(j), i.max(j))
+                })
+                .collect(),
+            Topology::Star => (1..n).map(|j| (0, j)).collect(),
+            Topology::RandomRegular(degree) => random_regular(n, degree, rng)?,
+        };
+        Ok(edges)
+    }
+}
+
+// 配对模型生成随机正则图，出现的自环和重边通过边交换修复，不重新生成
+fn random_regular(
+    n: usize,
+    degree: usize,
+    rng: &mut StdRng,
+) -> Result<Vec<(usize, usize)>, GossipSubError> {
+    if degree >= n || !(n * degree).is_multiple_of(2) {
+        return Err(GossipSubError::InvalidConfig(format!(
+            "无法生成 {} 个节点、度数为 {} 的随机正则图",
+            n, degree
+        )));
+    }
+
+    let ordered = |a: usize, b: usize| (a.min(b), a.max(b));
+    let mut stubs: Vec<usize> = (0..n).flat_map(|i| std::iter::repeat_n(i, degree)).collect();
+    stubs.shuffle(rng);
+    let mut edges: Vec<(usize, usize)> = stubs
+        .chunks(2)
+        .map(|pair| ordered(pair[0], pair[1]))
+        .collect();
+    let mut counts: HashMap<(usize, usize), usize> = HashMap::new();
+    for &edge in &edges {
+        *counts.entry(edge).or_default() += 1;
+    }
+
+    // 把冲突的边(a, b)和随机一条边(c, d)换成(a, c)和(b, d)，每个节点的度数不变
+    for _ in 0..MAX_REGULAR_SWITCHES {
+        let Some(i) = edges
+            .iter()
+            .position(|&(a, b)| a == b || counts[&(a, b)] > 1)
+        else {
+            edges.sort();
+            return Ok(edges);
+        };
+        let j = rng.random_range(0..edges.len());
+        let (a, b) = edges[i];
+        let (mut c, mut d) = edges[j];
+        if rng.random_bool(0.5) {
+            std::mem::swap(&mut c, &mut d);
+        }
+        let (first, second) = (ordered(a, c), ordered(b, d));
+        if i == j
+            || first.0 == first.1
+            || second.0 == second.1
+            || first == second
+            || counts.contains_key(&first)
+            || counts.contains_key(&second)
+        {
+            continue;
+        }
+
+        for old in [edges[i], edges[j]] {
+            if let Some(count) = counts.get_mut(&old) {
+                *count -= 1;
+                if *count == 0 {
+                    counts.remove(&old);
+                }
+            }
+        }
+        for new in [first, second] {
+            *counts.entry(new).or_default() += 1;
+        }
+        edges[i] = first;
+        edges[j] = second;
+    }
+
+    Err(GossipSubError::InvalidConfig(format!(
+        "{} 次边交换后仍未生成度数为 {} 的随机正则图",
+        MAX_REGULAR_SWITCHES, degree
+    )))
+}
+
+// 第i个节点的监听端口，base为0时由系统分配
+fn listen_port(base: u16, i: usize) -> Result<u16, GossipSubError> {
+    if base == 0 {
+        return Ok(0);
+    }
+    u16::try_from(i)
+        .ok()
+        .and_then(|i| base.checked_add(i))
+        .ok_or_else(|| {
+            GossipSubError::InvalidConfig(format!("端口 {} + {} 超出范围", base, i))
+        })
+}
+
+// 本地集群配置
+#[derive(Debug, Clone)]
+pub struct ClusterConfig {
+    pub nodes: usize,
+    pub topology: Topology,
+    pub topics: Vec<String>,
+    pub base_port: u16,        // 第i个节点监听 base_port + i，0表示由系统分配
+    pub messages: usize,       // 发布的消息数，0表示不发布
+    pub publish_interval: u64, // 两次发布之间的间隔(ms)
+    pub message_size: usize,   // 每条消息的字节数
+    pub warmup: u64,           // 连接后等待mesh稳定的时间(ms)
+    pub settle: u64,           // 发布结束后等待消息送达的时间(ms)
+    pub seed: u64,             // 决定拓扑和发布节点的选择
+    pub gossipsub: GossipSubConfig,
+}
+
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        Self {
+            nodes: 5,
+            topology: Topology::Full,
+            topics: vec!["cluster".to_string()],
+            base_port: 0,
+            messages: 10,
+            publish_interval: 100,
+            message_size: 64,
+            warmup: 3000,
+            settle: 2000,
+            seed: 42,
+            gossipsub: GossipSubConfig::default(),
+        }
+    }
+}
+
+// 单条消息的送达情况
+#[derive(Debug, Clone)]
+pub struct MessageDelivery {
+    pub message_id: String,
+    pub topic: String,
+    pub publisher: String,
+    pub expected: usize,                        // 应收到的节点数
+    pub delivered: BTreeMap<String, Duration>, // 节点 -> 从发布到收到的延迟
+}
+
+// 发布负载的送达汇总
+#[derive(Debug, Clone, Default)]
+pub struct LoadSummary {
+    pub messages: Vec<MessageDelivery>,
+    pub publish_errors: Vec<String>,
+}
+
+impl LoadSummary {
+    pub fn total_expected(&self) -> usize {
+        self.messages.iter().map(|m| m.expected).sum()
+    }
+
+    pub fn total_delivered(&self) -> usize {
+        self.messages.iter().map(|m| m.delivered.len()).sum()
+    }
+
+    pub fn delivery_ratio(&self) -> f64 {
+        match self.total_expected() {
+            0 => 1.0,
+            expected => self.total_delivered() as f64 / expected as f64,
+        }
+    }
+
+    pub fn mean_latency(&self) -> Option<Duration> {
+        let latencies: Vec<Duration> = self
+            .messages
+            .iter()
+            .flat_map(|m| m.delivered.values().copied())
+            .collect();
+        let total: Duration = latencies.iter().sum();
+        (!latencies.is_empty()).then(|| total / latencies.len() as u32)
+    }
+
+    pub fn max_latency(&self) -> Option<Duration> {
+        self.messages
+            .iter()
+            .flat_map(|m| m.delivered.values().copied())
+            .max()
+    }
+}
+
+// 在本机回环地址上运行的一组节点，使用真实的TCP传输
+pub struct Cluster {
+    pub config: ClusterConfig,
+    pub nodes: Vec<NodeHandle>,
+    pub edges: Vec<(usize, usize)>,
+    rng: StdRng,
+}
+
+impl Cluster {
+    // 启动所有节点，按拓扑连接并订阅主题，然后等待mesh稳定
+    pub async fn launch(config: ClusterConfig) -> Result<Self, GossipSubError> {
+        if config.nodes == 0 || config.topics.is_empty() {
+            return Err(GossipSubError::InvalidConfig(
+                "集群至少需要一个节点和一个主题".to_string(),
+            ));
+        }
+        config.gossipsub.validate()?;
+
+        let mut rng = StdRng::seed_from_u64(config.seed);
+        let edges = config.topology.edges(config.nodes, &mut rng)?;
+        // 启动任何节点之前检查端口范围
+        let ports = (0..config.nodes)
+            .map(|i| listen_port(config.base_port, i))
+            .collect::<Result<Vec<u16>, GossipSubError>>()?;
+
+        let mut nodes = Vec::with_capacity(config.nodes);
+        for (i, port) in ports.into_iter().enumerate() {
+            let mut gossipsub = config.gossipsub.clone();
+            gossipsub.rng_seed = gossipsub.rng_seed.or(Some(rng.random()));
+            let node = NodeHandle::spawn(NodeConfig {
+                node_id: Some(format!("node-{}", i)),
+                listen_addresses: vec![format!("127.0.0.1:{}", port)],
+                bootstrap_peers: Vec::new(),
+                gossipsub,
+                ..NodeConfig::default()
+            })
+            .await?;
+            for topic in &config.topics {
+                node.subscribe(topic).await?;
+            }
+            nodes.push(node);
+        }
+
+        for &(dialer, listener) in &edges {
+            nodes[dialer].dial(nodes[listener].listen_addresses[0].to_string())?;
+        }
+        info!(
+            nodes = config.nodes,
+            edges = edges.len(),
+            topology = %config.topology,
+            "集群已启动"
+        );
+        tokio::time::sleep(Duration::from_millis(config.warmup)).await;
+
+        Ok(Self {
+            config,
+            nodes,
+            edges,
+            rng,
+        })
+    }
+
+    // 轮流在各主题上从随机节点发布消息，统计每条消息的送达情况
+    pub async fn run_load(&mut self) -> Result<LoadSummary, GossipSubError> {
+        // 收集所有节点收到的消息 (节点, messageId, 收到时间)
+        let (received_tx, mut received_rx) = mpsc::unbounded_channel();
+        for node in &self.nodes {
+            let mut events = node.event_stream().await?;
+            let received_tx = received_tx.clone();
+            let peer_id = node.peer_id.clone();
+            tokio::spawn(async move {
+                while let Some(event) = events.recv().await {
+                    if let GossipSubEvent::MessageReceived { message, .. } = event
+                        && received_tx
+                            .send((peer_id.clone(), message.message_id, Instant::now()))
+                            .is_err()
+                    {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(received_tx);
+
+        let mut summary = LoadSummary::default();
+        let mut published_at = BTreeMap::new();
+        let topics = self.config.topics.clone();
+        for i in 0..self.config.messages {
+            let topic = &topics[i % topics.len()];
+            let publisher = &self.nodes[self.rng.random_range(0..self.nodes.len())];
+            let mut content = vec![0u8; self.config.message_size];
+            self.rng.fill(&mut content[..]);
+
+            let sent = Instant::now();
+            match publisher.publish(topic, content).await {
+                Ok(message_id) => {
+                    published_at.insert(message_id.clone(), (summary.messages.len(), sent));
+                    summary.messages.push(MessageDelivery {
+                        message_id,
+                        topic: topic.clone(),
+                        publisher: publisher.peer_id.clone(),
+                        expected: self.nodes.len() - 1,
+                        delivered: BTreeMap::new(),
+                    });
+                }
+                Err(e) => summary.publish_errors.push(e.to_string()),
+            }
+            tokio::time::sleep(Duration::from_millis(self.config.publish_interval)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(self.config.settle)).await;
+
+        while let Ok((peer_id, message_id, at)) = received_rx.try_recv() {
+            if let Some(&(index, sent)) = published_at.get(&message_id) {
+                summary.messages[index]
+                    .delivered
+                    .entry(peer_id)
+                    .or_insert(at.saturating_duration_since(sent));
+            }
+        }
+        Ok(summary)
+    }
+
+    // 各节点每个主题的mesh大小
+    pub async fn mesh_sizes(&self) -> Result<Vec<(String, Vec<usize>)>, GossipSubError> {
+        let mut sizes = Vec::with_capacity(self.nodes.len());
+        for node in &self.nodes {
+            let topics = self.config.topics.clone();
+            let mesh = node
+                .with_node(move |node| topics.iter().map(|t| node.get_mesh_size(t)).collect())
+                .await?;
+            sizes.push((node.peer_id.clone(), mesh));
+        }
+        Ok(sizes)
+    }
+
+    pub fn shutdown(&self) {
+        for node in &self.nodes {
+            node.shutdown();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn edges(topology: Topology, n: usize) -> Vec<(usize, usize)> {
+        let mut edges = topology.edges(n, &mut StdRng::seed_from_u64(1)).unwrap();
+        edges.sort();
+        edges
+    }
+
+    #[test]
+    fn topology_parses_and_displays() {
+        for name in ["full", "ring", "star", "random-regular:4"] {
+            assert_eq!(name.parse::<Topology>().unwrap().to_string(), name);
+        }
+        assert_eq!(
+            "random-regular".parse::<Topology>().unwrap(),
+            Topology::RandomRegular(DEFAULT_DEGREE)
+        );
+        assert!("mesh".parse::<Topology>().is_err());
+        assert!("random-regular:x".parse::<Topology>().is_err());
+    }
+
+    #[test]
+    fn topology_edges() {
+        assert_eq!(edges(Topology::Full, 3), vec![(0, 1), (0, 2), (1, 2)]);
+        assert_eq!(edges(Topology::Ring, 2), vec![(0, 1)]);
+        assert_eq!(edges(Topology::Ring, 4), vec![(0, 1), (0, 3), (1, 2), (2, 3)]);
+        assert_eq!(edges(Topology::Star, 3), vec![(0, 1), (0, 2)]);
+        assert!(edges(Topology::Full, 1).is_empty());
+    }
+
+    #[test]
+    fn random_regular_has_fixed_degree() {
+        let edges = edges(Topology::RandomRegular(3), 8);
+        assert_eq!(edges.len(), 12);
+        for node in 0..8 {
+            let degree = edges.iter().filter(|&&(a, b)| a == node || b == node).count();
+            assert_eq!(degree, 3);
+        }
+
+        let mut rng = StdRng::seed_from_u64(1);
+        assert!(Topology::RandomRegular(3).edges(5, &mut rng).is_err());
+        assert!(Topology::RandomRegular(4).edges(4, &mut rng).is_err());
+    }
+
+    #[test]
+    fn random_regular_repairs_dense_graphs() {
+        // 度数较高时配对模型几乎总会产生重边，需要靠边交换修复
+        for degree in 6..=8 {
+            for seed in 0..20 {
+                let mut rng = StdRng::seed_from_u64(seed);
+                let edges = Topology::RandomRegular(degree).edges(20, &mut rng).unwrap();
+                assert_eq!(edges.len(), 20 * degree / 2);
+                assert_eq!(edges.iter().collect::<HashSet<_>>().len(), edges.len());
+                assert!(edges.iter().all(|&(a, b)| a != b));
+                for node in 0..20 {
+                    let count = edges.iter().filter(|&&(a, b)| a == node || b == node).count();
+                    assert_eq!(count, degree);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn listen_port_is_checked() {
+        assert_eq!(listen_port(0, 70000).unwrap(), 0);
+        assert_eq!(listen_port(9000, 3).unwrap(), 9003);
+        assert_eq!(listen_port(u16::MAX, 0).unwrap(), u16::MAX);
+        assert!(matches!(listen_port(u16::MAX, 1), Err(GossipSubError::InvalidConfig(_))));
+        assert!(listen_port(1, 1 << 16).is_err());
+    }
+
+    #[tokio::test]
+    async fn launch_rejects_port_overflow() {
+        let config = ClusterConfig {
+            nodes: 2,
+            base_port: u16::MAX,
+            ..ClusterConfig::default()
+        };
+        assert!(matches!(
+            Cluster::launch(config).await,
+            Err(GossipSubError::InvalidConfig(_))
+        ));
+    }
+}
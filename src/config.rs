@@ -0,0 +1,283 @@
+use crate::error::GossipSubError;
+use crate::store::StoreConfig;
+use crate::types::GossipSubConfig;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::Path;
+use tracing::warn;
+
+// 环境变量覆盖的前缀，例如 GOSSIPSUB_MESH_SIZE=8
+pub const ENV_PREFIX: &str = "GOSSIPSUB_";
+// 环境变量中嵌套字段的分隔符，例如 GOSSIPSUB_SCORE_PARAMS__DECAY_TO_ZERO=0.05
+const ENV_NESTING: &str = "__";
+
+// 配置文件格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Toml,
+    Json,
+}
+
+impl ConfigFormat {
+    // 根据扩展名判断格式，默认为TOML
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => ConfigFormat::Json,
+            _ => ConfigFormat::Toml,
+        }
+    }
+}
+
+// 节点配置文件，缺省字段使用默认值
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodeConfig {
+    pub node_id: Option<String>,       // 节点ID，None时由程序生成
+    pub listen_addresses: Vec<String>, // 监听地址，例如 127.0.0.1:9000
+    pub bootstrap_peers: Vec<String>,  // 启动时连接的节点地址
+    pub gossipsub: GossipSubConfig,    // 协议参数
+    pub store: StoreConfig,            // 消息历史的存储位置和保留策略
+}
+
+impl NodeConfig {
+    // 读取配置文件（可选）并应用环境变量覆盖
+    pub fn load(path: Option<&Path>) -> Result<Self, GossipSubError> {
+        let value = match path {
+            Some(path) => {
+                let content = fs::read_to_string(path).map_err(|e| {
+                    GossipSubError::InvalidConfig(format!(
+                        "无法读取配置文件 {}: {}",
+                        path.display(),
+                        e
+                    ))
+                })?;
+                parse_value(&content, ConfigFormat::from_path(path))?
+            }
+            None => Value::Object(Map::new()),
+        };
+        Self::from_value(value, env_overrides())
+    }
+
+    // 解析配置内容，不应用环境变量
+    pub fn parse(content: &str, format: ConfigFormat) -> Result<Self, GossipSubError> {
+        Self::from_value(parse_value(content, format)?, Vec::new())
+    }
+
+    // 应用覆盖项后反序列化并校验，一次报告所有不合法的字段
+    pub fn from_value(
+        mut value: Value,
+        overrides: Vec<(String, String)>,
+    ) -> Result<Self, GossipSubError> {
+        let defaults = serde_json::to_value(NodeConfig::default())
+            .map_err(|e| GossipSubError::InvalidConfig(e.to_string()))?;
+        let Value::Object(provided) = &mut value else {
+            return Err(GossipSubError::InvalidConfig(
+                "配置文件的顶层必须是表/对象".to_string(),
+            ));
+        };
+
+        let mut errors = Vec::new();
+        for (name, raw) in overrides {
+            if let Err(reason) = apply_override(provided, &defaults, &name, &raw) {
+                errors.push(format!("{}{}: {}", ENV_PREFIX, name, reason));
+            }
+        }
+        if let Value::Object(default_fields) = &defaults {
+            collect_field_errors(&defaults, &mut Vec::new(), provided, default_fields, &mut errors);
+        }
+        if !errors.is_empty() {
+            return Err(GossipSubError::InvalidConfig(format!(
+                "{} 个字段不合法: {}",
+                errors.len(),
+                errors.join("; ")
+            )));
+        }
+
+        let config: NodeConfig = serde_json::from_value(value)
+            .map_err(|e| GossipSubError::InvalidConfig(e.to_string()))?;
+        config.gossipsub.validate()?;
+        Ok(config)
+    }
+}
+
+fn parse_value(content: &str, format: ConfigFormat) -> Result<Value, GossipSubError> {
+    let result = match format {
+        ConfigFormat::Toml => toml::from_str::<Value>(content).map_err(|e| e.to_string()),
+        ConfigFormat::Json => serde_json::from_str::<Value>(content).map_err(|e| e.to_string()),
+    };
+    result.map_err(|e| GossipSubError::InvalidConfig(format!("解析配置文件失败: {}", e)))
+}
+
+// 收集以ENV_PREFIX开头的环境变量，去掉前缀
+fn env_overrides() -> Vec<(String, String)> {
+    let mut overrides: Vec<(String, String)> = std::env::vars_os()
+        .filter_map(|(key, value)| {
+            let key = key.into_string().ok()?;
+            let name = key.strip_prefix(ENV_PREFIX)?.to_string();
+            Some((name, value.into_string().ok()?))
+        })
+        .collect();
+    overrides.sort();
+    overrides
+}
+
+// 将环境变量写入配置；NodeConfig之外的名称视为gossipsub下的字段，未知字段只警告并忽略
+fn apply_override(
+    provided: &mut Map<String, Value>,
+    defaults: &Value,
+    name: &str,
+    raw: &str,
+) -> Result<(), String> {
+    let mut path: Vec<String> = name
+        .split(ENV_NESTING)
+        .map(|segment| segment.to_ascii_lowercase())
+        .collect();
+    if defaults.get(&path[0]).is_none() {
+        path.insert(0, "gossipsub".to_string());
+    }
+
+    let mut expected = defaults;
+    for segment in &path {
+        let Some(field) = expected.get(segment) else {
+            warn!(variable = %format!("{}{}", ENV_PREFIX, name), "忽略未知的配置环境变量");
+            return Ok(());
+        };
+        expected = field;
+    }
+
+    // 候选值依次为JSON（数字、布尔值、数组和对象）、逗号分隔的列表和原始字符串，
+    // 取第一个放入默认配置后能反序列化的，例如 GOSSIPSUB_NODE_ID=7 仍是字符串
+    let mut candidates = Vec::new();
+    if let Ok(value) = serde_json::from_str::<Value>(raw)
+        && !value.is_string()
+    {
+        candidates.push(value);
+    }
+    if expected.is_array() {
+        candidates.push(Value::Array(
+            raw.split(',')
+                .map(|item| Value::String(item.trim().to_string()))
+                .collect(),
+        ));
+    }
+    candidates.push(Value::String(raw.to_string()));
+
+    let pointer = format!("/{}", path.join("/"));
+    let fits = |candidate: &Value| {
+        let mut config = defaults.clone();
+        let Some(slot) = config.pointer_mut(&pointer) else {
+            return false;
+        };
+        *slot = candidate.clone();
+        serde_json::from_value::<NodeConfig>(config).is_ok()
+    };
+    let position = candidates.iter().position(fits).unwrap_or(0);
+    let value = candidates.swap_remove(position);
+
+    let (last, parents) = path.split_last().ok_or("未知字段")?;
+    let mut target = provided;
+    for segment in parents {
+        let entry = target
+            .entry(segment.clone())
+            .or_insert_with(|| Value::Object(Map::new()));
+        target = entry.as_object_mut().ok_or("配置文件中对应的项不是表")?;
+    }
+    target.insert(last.clone(), value);
+    Ok(())
+}
+
+// 逐个字段检查：未知字段，或用默认配置替换该字段后仍无法反序列化
+fn collect_field_errors(
+    root_defaults: &Value,
+    path: &mut Vec<String>,
+    provided: &Map<String, Value>,
+    defaults: &Map<String, Value>,
+    errors: &mut Vec<String>,
+) {
+    for (key, value) in provided {
+        path.push(key.clone());
+        match (value, defaults.get(key)) {
+            (_, None) => errors.push(format!("{}: 未知字段", path.join("."))),
+            // 结构体字段递归检查，默认为空的对象（如direct_peers）整体检查
+            (Value::Object(inner), Some(Value::Object(inner_defaults)))
+                if !inner_defaults.is_empty() =>
+            {
+                collect_field_errors(root_defaults, path, inner, inner_defaults, errors);
+            }
+            _ => {
+                let mut candidate = root_defaults.clone();
+                let pointer = format!("/{}", path.join("/"));
+                if let Some(slot) = candidate.pointer_mut(&pointer) {
+                    *slot = value.clone();
+                }
+                if let Err(e) = serde_json::from_value::<NodeConfig>(candidate) {
+                    errors.push(format!("{}: {}", path.join("."), e));
+                }
+            }
+        }
+        path.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_env(overrides: &[(&str, &str)]) -> Result<NodeConfig, GossipSubError> {
+        let overrides = overrides
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        NodeConfig::from_value(Value::Object(Map::new()), overrides)
+    }
+
+    #[test]
+    fn env_overrides_top_level_and_gossipsub_fields() {
+        let config = with_env(&[
+            ("MESH_SIZE", "8"),
+            ("MESH_HIGH", "12"),
+            ("BOOTSTRAP_PEERS", "127.0.0.1:9000, 127.0.0.1:9001"),
+            ("SCORE_PARAMS__DECAY_TO_ZERO", "0.05"),
+        ])
+        .unwrap();
+
+        assert_eq!(config.gossipsub.mesh_size, 8);
+        assert_eq!(config.gossipsub.mesh_high, 12);
+        assert_eq!(config.bootstrap_peers, vec!["127.0.0.1:9000", "127.0.0.1:9001"]);
+        assert_eq!(config.gossipsub.score_params.decay_to_zero, 0.05);
+    }
+
+    #[test]
+    fn env_values_follow_the_field_type() {
+        let config = with_env(&[("NODE_ID", "7"), ("LISTEN_ADDRESSES", "[\"0.0.0.0:9000\"]")])
+            .unwrap();
+
+        assert_eq!(config.node_id.as_deref(), Some("7"));
+        assert_eq!(config.listen_addresses, vec!["0.0.0.0:9000"]);
+    }
+
+    #[test]
+    fn unknown_env_keys_are_ignored() {
+        let config = with_env(&[("NOT_A_FIELD", "1"), ("SCORE_PARAMS__NOPE", "2")]).unwrap();
+        assert_eq!(config.gossipsub.mesh_size, GossipSubConfig::default().mesh_size);
+    }
+
+    #[test]
+    fn invalid_env_value_is_reported() {
+        let Err(GossipSubError::InvalidConfig(reason)) = with_env(&[("MESH_SIZE", "many")]) else {
+            panic!("应当拒绝非数字的mesh_size");
+        };
+        assert!(reason.contains("mesh_size"));
+    }
+
+    #[test]
+    fn parse_rejects_unknown_fields_in_file() {
+        let toml = "[gossipsub]\nmesh_size = 8\nmesh_high = 12\n";
+        let config = NodeConfig::parse(toml, ConfigFormat::Toml).unwrap();
+        assert_eq!(config.gossipsub.mesh_size, 8);
+
+        let result = NodeConfig::parse("{\"gossipsub\": {\"mesh_sise\": 8}}", ConfigFormat::Json);
+        assert!(matches!(result, Err(GossipSubError::InvalidConfig(_))));
+    }
+}
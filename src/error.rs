@@ -0,0 +1,35 @@
+use std::fmt;
+
+// GossipSub错误类型
+#[derive(Debug, Clone, PartialEq)]
+pub enum GossipSubError {
+    NotSubscribed(String),                            // 未订阅的主题
+    MessageTooLarge { size: usize, max_size: usize }, // 消息超过max_transmit_size
+    InsufficientPeers(String),                        // 没有可以发布消息的对等节点
+    ValidationFailed(String),                         // 消息格式或内容不合法
+    Transport(String),                                // 发送消息失败
+    Codec(String),                                    // 消息编解码失败
+    InvalidConfig(String),                            // 配置参数不合法
+    Storage(String),                                  // 消息存储读写失败
+}
+
+impl fmt::Display for GossipSubError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GossipSubError::NotSubscribed(topic) => write!(f, "未订阅主题 {}", topic),
+            GossipSubError::MessageTooLarge { size, max_size } => {
+                write!(f, "消息过大: {} 字节 (上限 {} 字节)", size, max_size)
+            }
+            GossipSubError::InsufficientPeers(topic) => {
+                write!(f, "主题 {} 没有可以发送消息的对等节点", topic)
+            }
+            GossipSubError::ValidationFailed(reason) => write!(f, "消息校验失败: {}", reason),
+            GossipSubError::Transport(reason) => write!(f, "发送消息失败: {}", reason),
+            GossipSubError::Codec(reason) => write!(f, "消息编解码失败: {}", reason),
+            GossipSubError::InvalidConfig(reason) => write!(f, "配置不合法: {}", reason),
+            GossipSubError::Storage(reason) => write!(f, "消息存储失败: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for GossipSubError {}
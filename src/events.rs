@@ -0,0 +1,107 @@
+use crate::message::{GossipMessage, HistoryQuery};
+use crate::types::ConnectionDirection;
+
+// 节点加入mesh的原因
+#[derive(Debug, Clone, PartialEq)]
+pub enum GraftReason {
+    MeshInit,        // 订阅主题时初始化mesh
+    MeshMaintenance, // mesh不足时扩展
+    OutboundQuota,   // 补足出站连接配额
+    Opportunistic,   // mesh评分过低时的机会性GRAFT
+    RemoteRequest,   // 对方发送GRAFT
+}
+
+// 节点离开mesh的原因
+#[derive(Debug, Clone, PartialEq)]
+pub enum PruneReason {
+    MeshOverflow,     // mesh过大时收缩
+    Unsubscribed,     // 本节点取消订阅
+    PeerUnsubscribed, // 对方取消订阅
+    RemoteRequest,    // 对方发送PRUNE
+    PeerDisconnected, // 对方断开连接
+}
+
+// 拒绝GRAFT的原因
+#[derive(Debug, Clone, PartialEq)]
+pub enum GraftRejectReason {
+    NotSubscribed, // 本节点未订阅该主题
+    DirectPeer,    // 对方是直连节点
+    Backoff,       // 对方仍在PRUNE退避期内
+    Flood,         // 检测到GRAFT洪水攻击
+    MeshFull,      // mesh已满
+}
+
+// 无法响应IWANT的原因
+#[derive(Debug, Clone, PartialEq)]
+pub enum IWantUnfulfilledReason {
+    NotInCache,          // 消息不在缓存中
+    RetransmissionLimit, // 已达到gossip_retransmission上限
+}
+
+// 节点对外发出的协议事件
+#[derive(Debug, Clone)]
+pub enum GossipSubEvent {
+    PeerSubscribed {
+        peer_id: String,
+        topic: String,
+    },
+    PeerUnsubscribed {
+        peer_id: String,
+        topic: String,
+    },
+    Grafted {
+        peer_id: String,
+        topic: String,
+        direction: ConnectionDirection, // Outbound表示由本节点发起
+        reason: GraftReason,
+    },
+    Pruned {
+        peer_id: String,
+        topic: String,
+        direction: ConnectionDirection, // Outbound表示由本节点发起
+        reason: PruneReason,
+    },
+    GraftRejected {
+        peer_id: String,
+        topic: String,
+        reason: GraftRejectReason,
+    },
+    MessageReceived {
+        message: GossipMessage,
+        propagation_source: String, // 直接转发给我们的节点
+    },
+    MessageDuplicate {
+        message_id: String,
+        peer_id: String,
+    },
+    MessageInvalid {
+        message_id: String,
+        peer_id: String,
+        reason: String,
+    },
+    IWantServed {
+        peer_id: String,
+        message_id: String,
+    },
+    IWantUnfulfilled {
+        peer_id: String,
+        message_id: String,
+        reason: IWantUnfulfilledReason,
+    },
+    HistoryServed {
+        peer_id: String,
+        topic: String,
+        count: usize, // 返回的消息数
+    },
+    HistoryReceived {
+        peer_id: String,
+        topic: String,
+        message_ids: Vec<String>,   // 本页的消息ID，完整消息另外通过MessageReceived通知
+        next: Option<HistoryQuery>, // 下一页的查询，None表示没有更多消息
+    },
+    PeerScoreChanged {
+        peer_id: String,
+        old_score: f64,
+        new_score: f64,
+    },
+}
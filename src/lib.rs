@@ -1,7 +1,31 @@
-pub mod types;
+pub mod clock;
+pub mod config;
+pub mod transport;
+pub mod network;
+pub mod cluster;
+pub mod overlay;
+pub mod store;
+pub mod error;
+pub mod events;
 pub mod message;
+pub mod metrics;
 pub mod node;
+pub mod score;
+pub mod simulator;
+pub mod types;
 
-pub use types::*;
+pub use clock::*;
+pub use config::*;
+pub use transport::*;
+pub use network::*;
+pub use cluster::*;
+pub use overlay::*;
+pub use store::*;
+pub use error::*;
+pub use events::*;
 pub use message::*;
-pub use node::*;
\ No newline at end of file
+pub use metrics::*;
+pub use node::*;
+pub use score::*;
+pub use simulator::*;
+pub use types::*;
@@ -1,126 +1,565 @@
 use gossipsub_chat::*;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tracing_subscriber::EnvFilter;
 
-fn main() {
-    println!("=== GossipSub网络 - 第四步测试 ===");
+// 加入房间时显示的本地历史消息条数
+const HISTORY_ON_JOIN: usize = 20;
 
-    // 创建多个节点来测试mesh管理
-    let mut node1 = GossipSubNode::new("Node1".to_string());
-    let mut node2 = GossipSubNode::new("Node2".to_string());
-    let mut node3 = GossipSubNode::new("Node3".to_string());
-    let mut node4 = GossipSubNode::new("Node4".to_string());
-    let mut node5 = GossipSubNode::new("Node5".to_string());
+const USAGE: &str = "用法:
+  gossipsub-chat [chat] [--config <path>] [--listen <addr>]... [--bootstrap <addr>]... [--nick <name>]
+  gossipsub-chat simulate [--config <path>]
+  gossipsub-chat cluster [--config <path>] [--nodes <n>] [--topology full|ring|star|random-regular[:<k>]]
+                         [--topics <a,b>] [--messages <n>] [--interval <ms>] [--size <bytes>]
+                         [--warmup <ms>] [--settle <ms>] [--seed <n>] [--base-port <port>]";
 
-    // 建立完全连接的网络
-    let nodes = ["Node1", "Node2", "Node3", "Node4", "Node5"];
-    for i in 0..nodes.len() {
-        for j in 0..nodes.len() {
-            if i != j {
-                match nodes[i] {
-                    "Node1" => {
-                        node1.add_peer(nodes[j].to_string(), format!("connection_to_{}", nodes[j]))
-                    }
-                    "Node2" => {
-                        node2.add_peer(nodes[j].to_string(), format!("connection_to_{}", nodes[j]))
-                    }
-                    "Node3" => {
-                        node3.add_peer(nodes[j].to_string(), format!("connection_to_{}", nodes[j]))
-                    }
-                    "Node4" => {
-                        node4.add_peer(nodes[j].to_string(), format!("connection_to_{}", nodes[j]))
-                    }
-                    "Node5" => {
-                        node5.add_peer(nodes[j].to_string(), format!("connection_to_{}", nodes[j]))
+// 命令行参数
+struct Args {
+    command: String,
+    config_path: Option<PathBuf>,
+    listen: Vec<String>,    // 覆盖配置文件中的监听地址
+    bootstrap: Vec<String>, // 追加到配置文件中的启动节点
+    nick: Option<String>,
+    cluster: ClusterConfig, // cluster命令的参数
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = std::env::args().skip(1).peekable();
+    let command = match args.peek() {
+        Some(arg) if !arg.starts_with("--") => args.next().unwrap_or_default(),
+        _ => "chat".to_string(),
+    };
+    let mut parsed = Args {
+        command,
+        config_path: None,
+        listen: Vec::new(),
+        bootstrap: Vec::new(),
+        nick: None,
+        cluster: ClusterConfig::default(),
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} 需要一个参数", arg));
+        match arg.as_str() {
+            "--config" => parsed.config_path = Some(PathBuf::from(value()?)),
+            "--listen" => parsed.listen.push(value()?),
+            "--bootstrap" => parsed.bootstrap.push(value()?),
+            "--nick" => parsed.nick = Some(value()?),
+            "--nodes" => parsed.cluster.nodes = parse_number(&arg, value()?)?,
+            "--topology" => {
+                parsed.cluster.topology = value()?.parse().map_err(|e| format!("{}", e))?
+            }
+            "--topics" => {
+                parsed.cluster.topics = value()?
+                    .split(',')
+                    .map(|topic| topic.trim().to_string())
+                    .filter(|topic| !topic.is_empty())
+                    .collect()
+            }
+            "--messages" => parsed.cluster.messages = parse_number(&arg, value()?)?,
+            "--interval" => parsed.cluster.publish_interval = parse_number(&arg, value()?)?,
+            "--size" => parsed.cluster.message_size = parse_number(&arg, value()?)?,
+            "--warmup" => parsed.cluster.warmup = parse_number(&arg, value()?)?,
+            "--settle" => parsed.cluster.settle = parse_number(&arg, value()?)?,
+            "--seed" => parsed.cluster.seed = parse_number(&arg, value()?)?,
+            "--base-port" => parsed.cluster.base_port = parse_number(&arg, value()?)?,
+            other => return Err(format!("未知参数: {}", other)),
+        }
+    }
+    Ok(parsed)
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: String) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} 需要一个数字，实际为 {}", flag, value))
+}
+
+#[tokio::main]
+async fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("❌ {}\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    // 节点日志输出到stderr，级别由RUST_LOG控制；聊天时默认只显示警告，避免打断输入
+    let default_level = if args.command == "chat" { "warn" } else { "info" };
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default_level)),
+        )
+        .with_writer(std::io::stderr)
+        .init();
+
+    // 读取 --config <path> 指定的配置文件，并应用 GOSSIPSUB_* 环境变量覆盖
+    let mut config = match NodeConfig::load(args.config_path.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            std::process::exit(1);
+        }
+    };
+    if !args.listen.is_empty() {
+        config.listen_addresses = args.listen;
+    }
+    config.bootstrap_peers.extend(args.bootstrap);
+
+    let result = match args.command.as_str() {
+        "chat" => run_chat(config, args.nick).await,
+        "simulate" => {
+            run_simulation(&config);
+            Ok(())
+        }
+        "cluster" => {
+            let mut cluster = args.cluster;
+            cluster.gossipsub = config.gossipsub;
+            run_cluster(cluster).await
+        }
+        other => {
+            eprintln!("❌ 未知命令: {}\n{}", other, USAGE);
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("❌ {}", e);
+        std::process::exit(1);
+    }
+}
+
+// 聊天消息的内容，序列化后作为GossipMessage的content发布
+#[derive(Debug, Serialize, Deserialize)]
+struct ChatMessage {
+    nick: String,
+    text: String,
+}
+
+// 聊天客户端的状态
+struct ChatState {
+    node: NodeHandle,
+    nick: String,
+    rooms: Vec<String>,      // 已加入的房间
+    current: Option<String>, // 普通输入发送到的房间
+}
+
+async fn run_chat(config: NodeConfig, nick: Option<String>) -> Result<(), GossipSubError> {
+    let node = NodeHandle::spawn(config).await?;
+    let mut events = node.event_stream().await?;
+    let listen: Vec<String> = node.listen_addresses.iter().map(|a| a.to_string()).collect();
+    println!("节点 {} 正在监听 {}", node.peer_id, listen.join(", "));
+    println!("输入 /help 查看命令");
+
+    let mut state = ChatState {
+        nick: nick.unwrap_or_else(|| node.peer_id.clone()),
+        node,
+        rooms: Vec::new(),
+        current: None,
+    };
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        tokio::select! {
+            line = lines.next_line() => match line {
+                Ok(Some(line)) => {
+                    if !state.handle_line(line.trim()).await {
+                        break;
                     }
-                    _ => {}
                 }
+                _ => break,
+            },
+            Some(event) = events.recv() => state.print_event(event),
+        }
+    }
+
+    state.node.shutdown();
+    Ok(())
+}
+
+impl ChatState {
+    // 处理一行输入，返回false表示退出
+    async fn handle_line(&mut self, line: &str) -> bool {
+        if line.is_empty() {
+            return true;
+        }
+        let Some(command) = line.strip_prefix('/') else {
+            self.send(line).await;
+            return true;
+        };
+
+        let (name, arg) = match command.split_once(char::is_whitespace) {
+            Some((name, arg)) => (name, Some(arg.trim()).filter(|arg| !arg.is_empty())),
+            None => (command, None),
+        };
+        let result = match (name, arg) {
+            ("join", Some(room)) => self.join(room).await,
+            ("leave", room) => self.leave(room).await,
+            ("rooms", _) => {
+                self.print_rooms();
+                Ok(())
             }
+            ("peers", _) => self.print_peers().await,
+            ("mesh", room) => self.print_mesh(room).await,
+            ("nick", Some(nick)) => {
+                println!("* 昵称已改为 {}", nick);
+                self.nick = nick.to_string();
+                Ok(())
+            }
+            ("quit", _) => return false,
+            ("help", _) => {
+                print_help();
+                Ok(())
+            }
+            _ => {
+                println!("* 未知命令或缺少参数，输入 /help 查看命令");
+                Ok(())
+            }
+        };
+        if let Err(e) = result {
+            println!("❌ {}", e);
         }
+        true
     }
 
-    // 所有节点订阅相同主题
-    node1.subscribe("blockchain".to_string());
-    node2.subscribe("blockchain".to_string());
-    node3.subscribe("blockchain".to_string());
-    node4.subscribe("blockchain".to_string());
-    node5.subscribe("blockchain".to_string());
+    async fn send(&mut self, text: &str) {
+        let Some(room) = self.current.clone() else {
+            println!("* 还没有加入房间，使用 /join <room>");
+            return;
+        };
+        let message = ChatMessage {
+            nick: self.nick.clone(),
+            text: text.to_string(),
+        };
+        let content = match serde_json::to_vec(&message) {
+            Ok(content) => content,
+            Err(e) => {
+                println!("❌ {}", GossipSubError::Codec(e.to_string()));
+                return;
+            }
+        };
+        match self.node.publish(&room, content).await {
+            Ok(_) => println!(
+                "[{}] #{} <{}> {}",
+                format_time(GossipMessage::current_timestamp()),
+                room,
+                self.nick,
+                text
+            ),
+            Err(e) => println!("❌ {}", e),
+        }
+    }
 
-    println!("\n=== 初始mesh状态 ===");
-    println!("Node1 mesh大小: {}", node1.get_mesh_size("blockchain"));
-    println!("Node2 mesh大小: {}", node2.get_mesh_size("blockchain"));
-    println!("Node3 mesh大小: {}", node3.get_mesh_size("blockchain"));
+    async fn join(&mut self, room: &str) -> Result<(), GossipSubError> {
+        if !self.rooms.iter().any(|r| r == room) {
+            self.node.subscribe(room).await?;
+            self.rooms.push(room.to_string());
 
-    println!("\n=== 测试mesh维护 ===");
+            // 显示消息存储中保留的最近历史
+            let topic = room.to_string();
+            let history = self
+                .node
+                .with_node(move |node| {
+                    node.message_store()
+                        .map(|store| store.latest(&topic, u64::MAX, HISTORY_ON_JOIN))
+                        .unwrap_or_default()
+                })
+                .await?;
+            if !history.is_empty() {
+                println!("* #{} 最近 {} 条消息:", room, history.len());
+            }
+            for message in history {
+                print_chat_message(message);
+            }
+        }
+        self.current = Some(room.to_string());
+        println!("* 当前房间: #{}", room);
+        Ok(())
+    }
 
-    // 执行心跳来触发mesh维护
-    println!("\n--- Node1 执行心跳维护mesh ---");
-    match node1.gossip_heartbeat() {
-        Ok(_) => println!("✅ Node1 心跳成功"),
-        Err(e) => println!("❌ Node1 心跳失败: {}", e),
+    async fn leave(&mut self, room: Option<&str>) -> Result<(), GossipSubError> {
+        let Some(room) = room.map(str::to_string).or_else(|| self.current.clone()) else {
+            println!("* 还没有加入房间");
+            return Ok(());
+        };
+        self.node.unsubscribe(&room).await?;
+        self.rooms.retain(|r| *r != room);
+        if self.current.as_ref() == Some(&room) {
+            self.current = self.rooms.last().cloned();
+        }
+        println!("* 已离开 #{}", room);
+        Ok(())
+    }
+
+    fn print_rooms(&self) {
+        if self.rooms.is_empty() {
+            println!("* 还没有加入房间");
+        }
+        for room in &self.rooms {
+            let marker = if self.current.as_ref() == Some(room) { "*" } else { " " };
+            println!("{} #{}", marker, room);
+        }
     }
 
-    println!("Node1 mesh大小: {}", node1.get_mesh_size("blockchain"));
+    async fn print_peers(&self) -> Result<(), GossipSubError> {
+        let mut peers = self
+            .node
+            .with_node(|node| {
+                node.peers
+                    .iter()
+                    .map(|(peer_id, peer)| {
+                        let mut topics: Vec<String> = node
+                            .peer_topics
+                            .get(peer_id)
+                            .map(|topics| topics.iter().cloned().collect())
+                            .unwrap_or_default();
+                        topics.sort();
+                        (
+                            peer_id.clone(),
+                            peer.connection_info.clone(),
+                            peer.direction,
+                            node.peer_score(peer_id),
+                            topics,
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .await?;
+        peers.sort_by(|a, b| a.0.cmp(&b.0));
 
-    println!("\n=== 测试GRAFT/PRUNE消息处理 ===");
+        println!("* 已连接 {} 个节点", peers.len());
+        for (peer_id, address, direction, score, topics) in peers {
+            println!(
+                "  {} {} {:?} 评分 {:.2} 主题 [{}]",
+                peer_id,
+                address,
+                direction,
+                score,
+                topics.join(", ")
+            );
+        }
+        Ok(())
+    }
 
-    // 模拟Node2向Node1发送GRAFT请求
-    println!("\n--- 模拟GRAFT请求 ---");
-    let graft_message = GossipMessage::new(MessageType::Graft)
-        .with_topic("blockchain".to_string())
-        .with_from("Node2".to_string())
-        .with_to("Node1".to_string());
+    async fn print_mesh(&self, room: Option<&str>) -> Result<(), GossipSubError> {
+        let rooms: Vec<String> = match room {
+            Some(room) => vec![room.to_string()],
+            None => self.rooms.clone(),
+        };
+        let meshes = self
+            .node
+            .with_node(move |node| {
+                rooms
+                    .into_iter()
+                    .map(|room| {
+                        let mut peers: Vec<String> = node
+                            .mesh
+                            .get(&room)
+                            .map(|peers| peers.iter().cloned().collect())
+                            .unwrap_or_default();
+                        peers.sort();
+                        (room, peers)
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .await?;
 
-    match node1.handle_message(graft_message, "Node2") {
-        Ok(_) => println!("✅ Node1 成功处理GRAFT消息"),
-        Err(e) => println!("❌ Node1 处理GRAFT消息失败: {}", e),
+        for (room, peers) in meshes {
+            println!("* #{} mesh ({}): {}", room, peers.len(), peers.join(", "));
+        }
+        Ok(())
     }
 
-    // 模拟Node3向Node1发送PRUNE消息
-    println!("\n--- 模拟PRUNE消息 ---");
-    let prune_message = GossipMessage::new(MessageType::Prune)
-        .with_topic("blockchain".to_string())
-        .with_from("Node3".to_string())
-        .with_to("Node1".to_string());
+    fn print_event(&self, event: GossipSubEvent) {
+        match event {
+            GossipSubEvent::MessageReceived { message, .. } => print_chat_message(message),
+            GossipSubEvent::HistoryReceived {
+                peer_id,
+                topic,
+                message_ids,
+                ..
+            } if !message_ids.is_empty() => {
+                println!("* 已从 {} 同步 #{} 的 {} 条历史消息", peer_id, topic, message_ids.len());
+            }
+            GossipSubEvent::PeerSubscribed { peer_id, topic } if self.rooms.contains(&topic) => {
+                println!("* {} 加入了 #{}", peer_id, topic);
+            }
+            GossipSubEvent::PeerUnsubscribed { peer_id, topic } if self.rooms.contains(&topic) => {
+                println!("* {} 离开了 #{}", peer_id, topic);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn print_chat_message(message: GossipMessage) {
+    let room = message.topic.clone().unwrap_or_default();
+    let content = message.content.unwrap_or_default();
+    let (nick, text) = match serde_json::from_slice::<ChatMessage>(&content) {
+        Ok(chat) => (chat.nick, chat.text),
+        Err(_) => (
+            message.from.unwrap_or_else(|| "?".to_string()),
+            String::from_utf8_lossy(&content).into_owned(),
+        ),
+    };
+    println!(
+        "[{}] #{} <{}> {}",
+        format_time(message.timestamp),
+        room,
+        nick,
+        text
+    );
+}
+
+fn print_help() {
+    println!("命令:");
+    println!("  /join <room>    加入房间并设为当前房间");
+    println!("  /leave [room]   离开房间，默认当前房间");
+    println!("  /rooms          列出已加入的房间");
+    println!("  /peers          列出已连接的节点");
+    println!("  /mesh [room]    查看房间的mesh节点");
+    println!("  /nick <name>    修改昵称");
+    println!("  /quit           退出");
+    println!("其他输入会发送到当前房间");
+}
+
+// 将毫秒时间戳格式化为 HH:MM:SS (UTC)
+fn format_time(unix_millis: u64) -> String {
+    let seconds = unix_millis / 1000 % 86400;
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+// 在本机启动多个节点，按拓扑连接并运行发布负载
+async fn run_cluster(config: ClusterConfig) -> Result<(), GossipSubError> {
+    println!(
+        "=== 本地集群: {} 个节点, {} 拓扑, 主题 [{}] ===",
+        config.nodes,
+        config.topology,
+        config.topics.join(", ")
+    );
+    let mut cluster = Cluster::launch(config).await?;
+    println!("已建立 {} 条连接", cluster.edges.len());
 
-    match node1.handle_message(prune_message, "Node3") {
-        Ok(_) => println!("✅ Node1 成功处理PRUNE消息"),
-        Err(e) => println!("❌ Node1 处理PRUNE消息失败: {}", e),
+    println!("\n=== mesh状态 ===");
+    for (peer_id, sizes) in cluster.mesh_sizes().await? {
+        let sizes: Vec<String> = cluster
+            .config
+            .topics
+            .iter()
+            .zip(sizes)
+            .map(|(topic, size)| format!("#{} {}", topic, size))
+            .collect();
+        println!("{} {}", peer_id, sizes.join(", "));
     }
 
-    println!("Node1 最终mesh大小: {}", node1.get_mesh_size("blockchain"));
+    if cluster.config.messages > 0 {
+        println!("\n=== 发布负载 ===");
+        let summary = cluster.run_load().await?;
+        println!(
+            "发布 {} 条消息，应送达 {} 次，实际送达 {} 次 ({:.1}%)",
+            summary.messages.len(),
+            summary.total_expected(),
+            summary.total_delivered(),
+            summary.delivery_ratio() * 100.0
+        );
+        if let (Some(mean), Some(max)) = (summary.mean_latency(), summary.max_latency()) {
+            println!(
+                "平均延迟 {:.2} ms，最大延迟 {:.2} ms",
+                mean.as_secs_f64() * 1000.0,
+                max.as_secs_f64() * 1000.0
+            );
+        }
+        for message in summary
+            .messages
+            .iter()
+            .filter(|m| m.delivered.len() < m.expected)
+        {
+            println!(
+                "  消息 {} (#{}, 来自 {}) 只送达 {}/{} 个节点",
+                message.message_id,
+                message.topic,
+                message.publisher,
+                message.delivered.len(),
+                message.expected
+            );
+        }
+        for error in &summary.publish_errors {
+            println!("  ❌ 发布失败: {}", error);
+        }
+    }
+
+    cluster.shutdown();
+    Ok(())
+}
+
+// 在确定性的模拟网络中运行演示
+fn run_simulation(config: &NodeConfig) {
+    println!("=== GossipSub网络 - 模拟器测试 ===");
+
+    // 创建模拟网络：50ms延迟，10ms抖动，1%丢包
+    let mut simulator = Simulator::new(SimulatorConfig {
+        seed: 42,
+        network: NetworkConditions {
+            latency: 50,
+            jitter: 10,
+            loss_rate: 0.01,
+            bandwidth: None,
+        },
+    });
 
-    println!("\n=== 测试手动mesh扩展和收缩 ===");
+    let nodes: Vec<String> = (1..=5).map(|i| format!("Node{}", i)).collect();
+    for node_id in &nodes {
+        simulator.add_node(node_id, config.gossipsub.clone());
+    }
 
-    // 手动测试mesh扩展
-    println!("\n--- 测试mesh扩展 ---");
-    match node2.expand_mesh("blockchain") {
-        Ok(_) => println!("✅ Node2 mesh扩展成功"),
-        Err(e) => println!("❌ Node2 mesh扩展失败: {}", e),
+    // 建立完全连接的网络
+    for (i, dialer) in nodes.iter().enumerate() {
+        for listener in &nodes[i + 1..] {
+            simulator.connect(dialer, listener);
+        }
     }
-    println!("Node2 mesh大小: {}", node2.get_mesh_size("blockchain"));
 
-    // 手动测试mesh收缩
-    println!("\n--- 测试mesh收缩 ---");
-    match node3.contract_mesh("blockchain") {
-        Ok(_) => println!("✅ Node3 mesh收缩成功"),
-        Err(e) => println!("❌ Node3 mesh收缩失败: {}", e),
+    // 所有节点订阅相同主题
+    for node_id in &nodes {
+        simulator.subscribe(node_id, "blockchain");
     }
-    println!("Node3 mesh大小: {}", node3.get_mesh_size("blockchain"));
 
-    println!("\n=== 测试退避机制清理 ===");
-    node1.cleanup_backoffs();
-    println!("✅ Node1 执行了退避状态清理");
+    // 运行几次心跳，让订阅信息和mesh稳定下来
+    simulator.run_for(3000);
 
-    println!("\n第四步完成！我们新增了：");
-    println!("1. maintain_mesh() - mesh网络维护");
-    println!("2. expand_mesh() - mesh扩展（发送GRAFT）");
-    println!("3. contract_mesh() - mesh收缩（发送PRUNE）");
-    println!("4. handle_graft_message() - 处理GRAFT请求");
-    println!("5. handle_prune_message() - 处理PRUNE消息");
-    println!("6. prune_peer_from_mesh() - 剪除mesh节点");
-    println!("7. is_peer_in_backoff() - 退避状态检查");
-    println!("8. is_graft_flooding() - GRAFT洪水攻击检测");
-    println!("9. cleanup_backoffs() - 清理过期退避状态");
-    println!("10. 完整的mesh动态管理机制");
+    println!("\n=== mesh状态 ===");
+    for node in simulator.nodes() {
+        println!("{} mesh大小: {}", node.node_id, node.get_mesh_size("blockchain"));
+    }
+
+    println!("\n=== 发布消息 ===");
+    let message_id = match simulator.publish("Node1", "blockchain", b"hello gossipsub".to_vec()) {
+        Ok(message_id) => message_id,
+        Err(e) => {
+            println!("❌ 发布失败: {}", e);
+            return;
+        }
+    };
+    simulator.run_for(5000);
+
+    if let Some(report) = simulator.delivery_report(&message_id) {
+        println!(
+            "消息 {} 送达 {}/{} 个节点，最大延迟 {:?} ms",
+            message_id,
+            report.delivered_to.len(),
+            nodes.len() - 1,
+            report.max_latency()
+        );
+    }
 
-    println!("\n准备好继续下一步了吗？下一步我们将实现评分系统和节点声誉管理。");
+    let stats = &simulator.stats;
+    println!(
+        "网络统计: 发送 {} 条，投递 {} 条，丢弃 {} 条，共 {} 字节",
+        stats.sent, stats.delivered, stats.dropped, stats.bytes
+    );
 }
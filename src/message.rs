@@ -1,9 +1,28 @@
 use crate::types::MessageType;
+use serde::{Deserialize, Serialize};
 use std::time::{SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 
+// PRUNE中用于节点交换(PX)的peer信息
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeerInfo {
+    pub peer_id: String,
+    pub signed_peer_record: Option<Vec<u8>>, // peer记录，目前只是未签名的连接信息(UTF-8)
+}
+
+// 历史消息查询：时间范围内或某条消息之后的消息，按时间升序分页返回
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HistoryQuery {
+    pub from: Option<u64>,     // 起始时间戳(ms)，包含
+    pub to: Option<u64>,       // 结束时间戳(ms)，包含
+    pub after: Option<String>, // 从这条消息之后开始，对方没有该消息时按from查询
+    pub latest: bool,          // 没有after时从范围内最新的limit条开始，而不是最早的
+    pub limit: usize,          // 本页最多返回的消息数，对方会按自己的上限截断
+    pub ids_only: bool,        // 只返回消息ID
+}
+
 // GossipSub消息结构
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GossipMessage {
     pub message_type: MessageType,
     pub message_id: String,
@@ -13,6 +32,11 @@ pub struct GossipMessage {
     pub topic: Option<String>,
     pub content: Option<Vec<u8>>,
     pub message_ids: Vec<String>, // 用于IHAVE/IWANT
+    pub peers: Vec<PeerInfo>,     // 用于PRUNE的节点交换
+    pub backoff: Option<u64>,     // PRUNE要求对方等待的退避时间(ms)
+    pub history: Option<HistoryQuery>, // 历史请求的查询，响应中为下一页的查询
+    pub messages: Vec<GossipMessage>,  // 历史响应携带的消息
+    pub request_id: Option<String>,    // 历史响应对应的请求消息ID
 }
 
 impl GossipMessage {
@@ -26,9 +50,24 @@ impl GossipMessage {
             topic: None,
             content: None,
             message_ids: Vec::new(),
+            peers: Vec::new(),
+            backoff: None,
+            history: None,
+            messages: Vec::new(),
+            request_id: None,
         }
     }
 
+    pub fn with_message_id(mut self, message_id: String) -> Self {
+        self.message_id = message_id;
+        self
+    }
+
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
     pub fn with_topic(mut self, topic: String) -> Self {
         self.topic = Some(topic);
         self
@@ -44,6 +83,31 @@ impl GossipMessage {
         self
     }
 
+    pub fn with_peers(mut self, peers: Vec<PeerInfo>) -> Self {
+        self.peers = peers;
+        self
+    }
+
+    pub fn with_backoff(mut self, backoff: u64) -> Self {
+        self.backoff = Some(backoff);
+        self
+    }
+
+    pub fn with_history(mut self, history: HistoryQuery) -> Self {
+        self.history = Some(history);
+        self
+    }
+
+    pub fn with_messages(mut self, messages: Vec<GossipMessage>) -> Self {
+        self.messages = messages;
+        self
+    }
+
+    pub fn with_request_id(mut self, request_id: String) -> Self {
+        self.request_id = Some(request_id);
+        self
+    }
+
     pub fn with_to(mut self, to: String) -> Self {
         self.to = Some(to);
         self
@@ -54,6 +118,32 @@ impl GossipMessage {
         self
     }
 
+    // 估算消息的传输大小(字节)
+    pub fn encoded_len(&self) -> usize {
+        let optional_len = |field: &Option<String>| field.as_ref().map_or(0, |s| s.len());
+
+        self.message_id.len()
+            + std::mem::size_of::<u64>()
+            + self.backoff.map_or(0, |_| std::mem::size_of::<u64>())
+            + optional_len(&self.from)
+            + optional_len(&self.to)
+            + optional_len(&self.topic)
+            + optional_len(&self.request_id)
+            + self.content.as_ref().map_or(0, |c| c.len())
+            + self.message_ids.iter().map(|id| id.len()).sum::<usize>()
+            + self
+                .peers
+                .iter()
+                .map(|peer| {
+                    peer.peer_id.len() + peer.signed_peer_record.as_ref().map_or(0, |r| r.len())
+                })
+                .sum::<usize>()
+            + self.history.as_ref().map_or(0, |query| {
+                optional_len(&query.after) + 3 * std::mem::size_of::<u64>()
+            })
+            + self.messages.iter().map(|m| m.encoded_len()).sum::<usize>()
+    }
+
     fn generate_id() -> String {
         Uuid::new_v4().to_string()
     }
@@ -61,7 +151,6 @@ impl GossipMessage {
     pub fn current_timestamp() -> u64 {
         SystemTime::now()
             .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as u64
+            .map_or(0, |d| d.as_millis() as u64)
     }
 }
@@ -0,0 +1,375 @@
+use crate::node::GossipSubNode;
+use crate::types::MessageType;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::time::Duration;
+
+// 心跳耗时直方图的桶上限(秒)
+const HEARTBEAT_BUCKETS: [f64; 7] = [0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0];
+// 节点评分直方图的桶上限
+const SCORE_BUCKETS: [f64; 7] = [-100.0, -10.0, -1.0, 0.0, 1.0, 10.0, 100.0];
+
+// 控制消息和发布消息的类型，按固定顺序导出
+const MESSAGE_TYPES: [MessageType; 9] = [
+    MessageType::Publish,
+    MessageType::IHave,
+    MessageType::IWant,
+    MessageType::Graft,
+    MessageType::Prune,
+    MessageType::Subscribe,
+    MessageType::Unsubscribe,
+    MessageType::HistoryRequest,
+    MessageType::HistoryResponse,
+];
+
+// 协议运行指标
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub published: HashMap<String, u64>,   // topic -> 本节点发布的消息数
+    pub received: HashMap<String, u64>,    // topic -> 收到的新消息数
+    pub duplicates: u64,                   // 收到的重复消息数
+    pub invalid: u64,                      // 因超大或格式错误被丢弃的消息数
+    pub sent_by_type: RefCell<HashMap<MessageType, u64>>, // 按类型统计发送的消息数
+    pub received_by_type: HashMap<MessageType, u64>,      // 按类型统计收到的消息数
+    pub heartbeat_buckets: [u64; HEARTBEAT_BUCKETS.len()], // 心跳耗时直方图（非累计）
+    pub heartbeat_count: u64,
+    pub heartbeat_seconds_sum: f64,
+}
+
+impl Metrics {
+    pub fn record_published(&mut self, topic: &str) {
+        *self.published.entry(topic.to_string()).or_default() += 1;
+    }
+
+    pub fn record_received(&mut self, message_type: &MessageType, topic: Option<&str>) {
+        *self.received_by_type.entry(message_type.clone()).or_default() += 1;
+        if *message_type == MessageType::Publish
+            && let Some(topic) = topic
+        {
+            *self.received.entry(topic.to_string()).or_default() += 1;
+        }
+    }
+
+    // 发送消息只持有&self，因此使用RefCell计数
+    pub fn record_sent(&self, message_type: &MessageType) {
+        *self
+            .sent_by_type
+            .borrow_mut()
+            .entry(message_type.clone())
+            .or_default() += 1;
+    }
+
+    pub fn observe_heartbeat(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(bucket) = HEARTBEAT_BUCKETS.iter().position(|&le| seconds <= le) {
+            self.heartbeat_buckets[bucket] += 1;
+        }
+        self.heartbeat_count += 1;
+        self.heartbeat_seconds_sum += seconds;
+    }
+}
+
+impl GossipSubNode {
+    // 以Prometheus文本格式导出指标
+    pub fn export_metrics(&self) -> String {
+        let mut out = String::new();
+        let metrics = &self.metrics;
+
+        // 每个主题的mesh、fanout和订阅节点数
+        let mesh_sizes: BTreeMap<&str, usize> = self
+            .mesh
+            .iter()
+            .map(|(topic, peers)| (topic.as_str(), peers.len()))
+            .collect();
+        write_gauge_by_topic(&mut out, "gossipsub_mesh_peers", "mesh中的节点数", &mesh_sizes);
+
+        let fanout_sizes: BTreeMap<&str, usize> = self
+            .fanout
+            .iter()
+            .map(|(topic, peers)| (topic.as_str(), peers.len()))
+            .collect();
+        write_gauge_by_topic(
+            &mut out,
+            "gossipsub_fanout_peers",
+            "fanout中的节点数",
+            &fanout_sizes,
+        );
+
+        let mut topic_peers: BTreeMap<&str, usize> = BTreeMap::new();
+        for topics in self.peer_topics.values() {
+            for topic in topics {
+                *topic_peers.entry(topic.as_str()).or_default() += 1;
+            }
+        }
+        write_gauge_by_topic(
+            &mut out,
+            "gossipsub_topic_peers",
+            "订阅该主题的已知节点数",
+            &topic_peers,
+        );
+
+        // 消息计数
+        write_counter_by_topic(
+            &mut out,
+            "gossipsub_messages_published_total",
+            "本节点发布的消息数",
+            &metrics.published,
+        );
+        write_counter_by_topic(
+            &mut out,
+            "gossipsub_messages_received_total",
+            "收到的新消息数",
+            &metrics.received,
+        );
+        write_metric(
+            &mut out,
+            "gossipsub_messages_duplicate_total",
+            "收到的重复消息数",
+            "counter",
+            &[("", metrics.duplicates as f64)],
+        );
+        write_metric(
+            &mut out,
+            "gossipsub_messages_invalid_total",
+            "被丢弃的非法消息数",
+            "counter",
+            &[("", metrics.invalid as f64)],
+        );
+
+        // 按类型统计的收发消息数（包括IHAVE/IWANT/GRAFT/PRUNE）
+        let sent_by_type = metrics.sent_by_type.borrow();
+        let sent: Vec<(String, f64)> = MESSAGE_TYPES
+            .iter()
+            .map(|t| {
+                let label = format!("type=\"{:?}\"", t);
+                (label, sent_by_type.get(t).copied().unwrap_or(0) as f64)
+            })
+            .collect();
+        write_labeled(
+            &mut out,
+            "gossipsub_rpc_sent_total",
+            "按类型统计发送的消息数",
+            "counter",
+            &sent,
+        );
+        let received: Vec<(String, f64)> = MESSAGE_TYPES
+            .iter()
+            .map(|t| {
+                let label = format!("type=\"{:?}\"", t);
+                (label, metrics.received_by_type.get(t).copied().unwrap_or(0) as f64)
+            })
+            .collect();
+        write_labeled(
+            &mut out,
+            "gossipsub_rpc_received_total",
+            "按类型统计收到的消息数",
+            "counter",
+            &received,
+        );
+
+        // gossip速率限制
+        let limits = &self.gossip_limit_stats;
+        write_labeled(
+            &mut out,
+            "gossipsub_gossip_ignored_total",
+            "超出速率限制而被忽略的gossip",
+            "counter",
+            &[
+                ("kind=\"ihave\"".to_string(), limits.ignored_ihave as f64),
+                ("kind=\"iwant_id\"".to_string(), limits.ignored_iwant_ids as f64),
+                (
+                    "kind=\"retransmission\"".to_string(),
+                    limits.ignored_retransmissions as f64,
+                ),
+                (
+                    "kind=\"history_request\"".to_string(),
+                    limits.ignored_history_requests as f64,
+                ),
+            ],
+        );
+
+        // 缓存大小
+        write_metric(
+            &mut out,
+            "gossipsub_message_cache_size",
+            "message_cache中的消息数",
+            "gauge",
+            &[("", self.message_cache.len() as f64)],
+        );
+        write_metric(
+            &mut out,
+            "gossipsub_seen_messages_size",
+            "seen_messages中的消息ID数",
+            "gauge",
+            &[("", self.seen_messages.len() as f64)],
+        );
+
+        // 已连接节点的评分分布
+        let scores: Vec<f64> = self
+            .peers
+            .keys()
+            .map(|peer_id| self.peer_score(peer_id))
+            .collect();
+        let mut score_buckets = [0u64; SCORE_BUCKETS.len()];
+        for score in &scores {
+            if let Some(bucket) = SCORE_BUCKETS.iter().position(|&le| *score <= le) {
+                score_buckets[bucket] += 1;
+            }
+        }
+        write_histogram(
+            &mut out,
+            "gossipsub_peer_score",
+            "已连接节点的评分分布",
+            &SCORE_BUCKETS,
+            &score_buckets,
+            scores.len() as u64,
+            scores.iter().sum(),
+        );
+
+        // 心跳耗时
+        write_histogram(
+            &mut out,
+            "gossipsub_heartbeat_duration_seconds",
+            "心跳耗时(秒)",
+            &HEARTBEAT_BUCKETS,
+            &metrics.heartbeat_buckets,
+            metrics.heartbeat_count,
+            metrics.heartbeat_seconds_sum,
+        );
+
+        out
+    }
+}
+
+// 转义Prometheus标签值
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn write_header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn write_metric(out: &mut String, name: &str, help: &str, kind: &str, samples: &[(&str, f64)]) {
+    write_header(out, name, help, kind);
+    for (labels, value) in samples {
+        if labels.is_empty() {
+            let _ = writeln!(out, "{} {}", name, value);
+        } else {
+            let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+        }
+    }
+}
+
+fn write_labeled(out: &mut String, name: &str, help: &str, kind: &str, samples: &[(String, f64)]) {
+    write_header(out, name, help, kind);
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+    }
+}
+
+fn write_gauge_by_topic(out: &mut String, name: &str, help: &str, values: &BTreeMap<&str, usize>) {
+    write_header(out, name, help, "gauge");
+    for (topic, value) in values {
+        let _ = writeln!(out, "{}{{topic=\"{}\"}} {}", name, escape_label(topic), value);
+    }
+}
+
+fn write_counter_by_topic(out: &mut String, name: &str, help: &str, values: &HashMap<String, u64>) {
+    let sorted: BTreeMap<&String, &u64> = values.iter().collect();
+    write_header(out, name, help, "counter");
+    for (topic, value) in sorted {
+        let _ = writeln!(out, "{}{{topic=\"{}\"}} {}", name, escape_label(topic), value);
+    }
+}
+
+fn write_histogram(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    bounds: &[f64],
+    buckets: &[u64],
+    count: u64,
+    sum: f64,
+) {
+    write_header(out, name, help, "histogram");
+    let mut cumulative = 0;
+    for (le, bucket) in bounds.iter().zip(buckets) {
+        cumulative += bucket;
+        let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, le, cumulative);
+    }
+    let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+    let _ = writeln!(out, "{}_sum {}", name, sum);
+    let _ = writeln!(out, "{}_count {}", name, count);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::GossipMessage;
+
+    #[test]
+    fn export_reports_mesh_messages_and_caches() {
+        let mut node = GossipSubNode::new("local".to_string());
+        let _outbound = node.outbound_stream();
+        node.add_peer("peer".to_string(), "peer-addr".to_string());
+        node.subscribe("topic".to_string());
+        node.publish("topic", b"hello".to_vec()).unwrap();
+        let message = GossipMessage::new(MessageType::Publish)
+            .with_topic("topic".to_string())
+            .with_from("peer".to_string());
+        node.handle_message(message.clone(), "peer").unwrap();
+        node.handle_message(message, "peer").unwrap();
+
+        let out = node.export_metrics();
+
+        for line in [
+            "# TYPE gossipsub_mesh_peers gauge",
+            "gossipsub_mesh_peers{topic=\"topic\"} 1",
+            "gossipsub_messages_published_total{topic=\"topic\"} 1",
+            "gossipsub_messages_received_total{topic=\"topic\"} 1",
+            "gossipsub_messages_duplicate_total 1",
+            "gossipsub_rpc_sent_total{type=\"Publish\"} 1",
+            "gossipsub_rpc_received_total{type=\"Publish\"} 1",
+            "gossipsub_seen_messages_size 2",
+            "gossipsub_peer_score_count 1",
+        ] {
+            assert!(out.lines().any(|l| l == line), "缺少指标行: {}", line);
+        }
+    }
+
+    #[test]
+    fn heartbeat_histogram_is_cumulative() {
+        let mut metrics = Metrics::default();
+        metrics.observe_heartbeat(Duration::from_micros(500));
+        metrics.observe_heartbeat(Duration::from_millis(20));
+        metrics.observe_heartbeat(Duration::from_secs(2));
+        let mut out = String::new();
+
+        write_histogram(
+            &mut out,
+            "heartbeat",
+            "心跳耗时",
+            &HEARTBEAT_BUCKETS,
+            &metrics.heartbeat_buckets,
+            metrics.heartbeat_count,
+            metrics.heartbeat_seconds_sum,
+        );
+
+        assert!(out.contains("heartbeat_bucket{le=\"0.001\"} 1\n"));
+        assert!(out.contains("heartbeat_bucket{le=\"0.05\"} 2\n"));
+        assert!(out.contains("heartbeat_bucket{le=\"1\"} 2\n"));
+        assert!(out.contains("heartbeat_bucket{le=\"+Inf\"} 3\n"));
+        assert!(out.contains("heartbeat_count 3\n"));
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        assert_eq!(escape_label("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
@@ -0,0 +1,390 @@
+use crate::config::NodeConfig;
+use crate::error::GossipSubError;
+use crate::events::GossipSubEvent;
+use crate::message::GossipMessage;
+use crate::node::GossipSubNode;
+use crate::transport::{self, ConnectionEvent, LocalIdentity};
+use crate::types::ConnectionDirection;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{self, Receiver, Sender, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, trace, warn};
+use uuid::Uuid;
+
+// 未配置监听地址时使用的默认地址
+pub const DEFAULT_LISTEN_ADDRESS: &str = "127.0.0.1:0";
+
+// 在节点运行时中执行的操作
+type NodeTask = Box<dyn FnOnce(&mut GossipSubNode) + Send>;
+
+// 发送给节点运行时的命令
+enum Command {
+    Dial(String),
+    Run(NodeTask),
+    Shutdown,
+}
+
+// 运行在TCP传输上的节点的句柄，可以克隆后在多个任务中使用
+#[derive(Clone)]
+pub struct NodeHandle {
+    pub peer_id: String,
+    pub listen_addresses: Vec<SocketAddr>, // 实际绑定的监听地址
+    commands: UnboundedSender<Command>,
+}
+
+impl NodeHandle {
+    // 启动节点：绑定监听地址，连接启动节点，并在后台运行协议
+    pub async fn spawn(config: NodeConfig) -> Result<Self, GossipSubError> {
+        // 节点本身不校验配置，在绑定端口前拒绝不合法的参数
+        config.gossipsub.validate()?;
+
+        let peer_id = config
+            .node_id
+            .clone()
+            .unwrap_or_else(|| format!("node-{}", &Uuid::new_v4().to_string()[..8]));
+        let mut listen_addresses = config.listen_addresses.clone();
+        if listen_addresses.is_empty() {
+            listen_addresses.push(DEFAULT_LISTEN_ADDRESS.to_string());
+        }
+
+        let mut listeners = Vec::new();
+        let mut bound = Vec::new();
+        for address in &listen_addresses {
+            let listener = TcpListener::bind(address)
+                .await
+                .map_err(|e| GossipSubError::Transport(format!("无法监听 {}: {}", address, e)))?;
+            bound.push(
+                listener
+                    .local_addr()
+                    .map_err(|e| GossipSubError::Transport(e.to_string()))?,
+            );
+            listeners.push(listener);
+        }
+
+        let identity = LocalIdentity {
+            peer_id: peer_id.clone(),
+            listen_address: bound[0].to_string(),
+        };
+        let (events_tx, events_rx) = mpsc::channel(transport::EVENT_QUEUE_SIZE);
+        let (commands_tx, commands_rx) = mpsc::unbounded_channel();
+        let next_connection_id = Arc::new(AtomicU64::new(0));
+
+        let accept_tasks = listeners
+            .into_iter()
+            .map(|listener| {
+                tokio::spawn(accept_loop(
+                    listener,
+                    identity.clone(),
+                    next_connection_id.clone(),
+                    events_tx.clone(),
+                ))
+            })
+            .collect();
+
+        let mut node = GossipSubNode::with_config(peer_id.clone(), config.gossipsub);
+        node.set_message_store(config.store.open()?);
+        let outbound = node.outbound_stream();
+
+        info!(node_id = %peer_id, listen = ?bound, "节点开始监听");
+        let runtime = Runtime {
+            node,
+            outbound,
+            identity,
+            connections: HashMap::new(),
+            dialing: HashMap::new(),
+            next_connection_id,
+            events_tx,
+            accept_tasks,
+        };
+        tokio::spawn(runtime.run(commands_rx, events_rx));
+
+        let handle = Self {
+            peer_id,
+            listen_addresses: bound,
+            commands: commands_tx,
+        };
+        for address in config.bootstrap_peers {
+            handle.dial(address)?;
+        }
+        Ok(handle)
+    }
+
+    // 连接指定地址的节点
+    pub fn dial(&self, address: String) -> Result<(), GossipSubError> {
+        self.send(Command::Dial(address))
+    }
+
+    // 在节点运行时中访问节点状态，执行后发送产生的消息
+    pub async fn with_node<R, F>(&self, f: F) -> Result<R, GossipSubError>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut GossipSubNode) -> R + Send + 'static,
+    {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.send(Command::Run(Box::new(move |node| {
+            let _ = reply_tx.send(f(node));
+        })))?;
+        reply_rx.await.map_err(|_| stopped())
+    }
+
+    pub async fn subscribe(&self, topic: &str) -> Result<(), GossipSubError> {
+        let topic = topic.to_string();
+        self.with_node(move |node| node.subscribe(topic)).await
+    }
+
+    pub async fn unsubscribe(&self, topic: &str) -> Result<(), GossipSubError> {
+        let topic = topic.to_string();
+        self.with_node(move |node| node.unsubscribe(&topic)).await?
+    }
+
+    pub async fn publish(&self, topic: &str, content: Vec<u8>) -> Result<String, GossipSubError> {
+        let topic = topic.to_string();
+        self.with_node(move |node| node.publish(&topic, content))
+            .await?
+    }
+
+    // 订阅节点的协议事件
+    pub async fn event_stream(&self) -> Result<UnboundedReceiver<GossipSubEvent>, GossipSubError> {
+        self.with_node(|node| node.event_stream()).await
+    }
+
+    // 停止节点，关闭监听和所有连接
+    pub fn shutdown(&self) {
+        let _ = self.send(Command::Shutdown);
+    }
+
+    fn send(&self, command: Command) -> Result<(), GossipSubError> {
+        self.commands.send(command).map_err(|_| stopped())
+    }
+}
+
+fn stopped() -> GossipSubError {
+    GossipSubError::Transport("节点已停止".to_string())
+}
+
+async fn accept_loop(
+    listener: TcpListener,
+    identity: LocalIdentity,
+    next_connection_id: Arc<AtomicU64>,
+    events: Sender<ConnectionEvent>,
+) {
+    loop {
+        let (stream, remote) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!(error = %e, "接受连接失败");
+                continue;
+            }
+        };
+        trace!(%remote, "收到入站连接");
+        let connection_id = next_connection_id.fetch_add(1, Ordering::Relaxed);
+        let identity = identity.clone();
+        let events = events.clone();
+        tokio::spawn(async move {
+            if let Err(e) = transport::run_connection(
+                stream,
+                identity,
+                ConnectionDirection::Inbound,
+                connection_id,
+                events,
+            )
+            .await
+            {
+                debug!(%remote, error = %e, "入站连接握手失败");
+            }
+        });
+    }
+}
+
+// 节点运行时：独占GossipSubNode，在传输事件、命令和心跳之间调度
+struct Runtime {
+    node: GossipSubNode,
+    outbound: UnboundedReceiver<(String, GossipMessage)>, // 节点待发送的消息
+    identity: LocalIdentity,
+    connections: HashMap<String, (u64, Sender<GossipMessage>)>, // peerId -> (连接ID, 发送端)
+    dialing: HashMap<u64, String>, // 连接ID -> 正在拨号的地址
+    next_connection_id: Arc<AtomicU64>,
+    events_tx: Sender<ConnectionEvent>,
+    accept_tasks: Vec<JoinHandle<()>>,
+}
+
+impl Runtime {
+    async fn run(
+        mut self,
+        mut commands: UnboundedReceiver<Command>,
+        mut events: Receiver<ConnectionEvent>,
+    ) {
+        let interval = Duration::from_millis(self.node.config.heartbeat_interval.max(1));
+        let mut heartbeat = tokio::time::interval(interval);
+        heartbeat.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        // 构造节点时加入的直连节点需要先拨号
+        self.dial_unconnected_peers();
+        loop {
+            tokio::select! {
+                command = commands.recv() => match command {
+                    Some(Command::Dial(address)) => self.dial(address),
+                    Some(Command::Run(task)) => task(&mut self.node),
+                    Some(Command::Shutdown) | None => break,
+                },
+                Some(event) = events.recv() => self.handle_connection_event(event),
+                _ = heartbeat.tick() => {
+                    let _ = self.node.gossip_heartbeat();
+                }
+            }
+            self.dial_unconnected_peers();
+            self.flush();
+        }
+
+        info!(node_id = %self.identity.peer_id, "节点已停止");
+        for task in &self.accept_tasks {
+            task.abort();
+        }
+    }
+
+    fn handle_connection_event(&mut self, event: ConnectionEvent) {
+        match event {
+            ConnectionEvent::Established {
+                connection_id,
+                peer_id,
+                listen_address,
+                direction,
+                sender,
+            } => {
+                self.dialing.remove(&connection_id);
+                self.on_established(connection_id, peer_id, listen_address, direction, sender);
+            }
+            ConnectionEvent::Message { peer_id, message } => {
+                if !self.connections.contains_key(&peer_id) {
+                    return;
+                }
+                if let Err(e) = self.node.handle_message(*message, &peer_id) {
+                    debug!(peer = %peer_id, error = %e, "处理消息失败");
+                }
+            }
+            ConnectionEvent::Closed {
+                connection_id,
+                peer_id,
+            } => {
+                // 只处理当前使用的连接，被替换的重复连接直接忽略
+                if self
+                    .connections
+                    .get(&peer_id)
+                    .is_some_and(|(id, _)| *id == connection_id)
+                {
+                    self.connections.remove(&peer_id);
+                    self.node.remove_peer(&peer_id);
+                }
+            }
+            ConnectionEvent::DialFailed { address, error } => {
+                self.dialing.retain(|_, dialing| *dialing != address);
+                warn!(node_id = %self.identity.peer_id, address, error = %error, "拨号失败");
+            }
+        }
+    }
+
+    fn on_established(
+        &mut self,
+        connection_id: u64,
+        peer_id: String,
+        listen_address: String,
+        direction: ConnectionDirection,
+        sender: Sender<GossipMessage>,
+    ) {
+        if self.connections.contains_key(&peer_id) {
+            // 双方同时拨号时，统一保留peerId较小一方发起的连接
+            let local_is_dialer = direction == ConnectionDirection::Outbound;
+            if local_is_dialer != (self.identity.peer_id < peer_id) {
+                trace!(peer = %peer_id, "丢弃重复连接");
+                return;
+            }
+            if let Some(peer) = self.node.peers.get_mut(&peer_id) {
+                peer.direction = direction;
+            }
+            self.connections.insert(peer_id, (connection_id, sender));
+            return;
+        }
+
+        self.connections
+            .insert(peer_id.clone(), (connection_id, sender));
+        match direction {
+            ConnectionDirection::Outbound => self.node.add_peer(peer_id, listen_address),
+            ConnectionDirection::Inbound => self.node.add_inbound_peer(peer_id, listen_address),
+        }
+    }
+
+    fn dial(&mut self, address: String) {
+        if self.dialing.values().any(|dialing| *dialing == address) {
+            return;
+        }
+        let connection_id = self.next_connection_id.fetch_add(1, Ordering::Relaxed);
+        debug!(node_id = %self.identity.peer_id, address, "拨号");
+        self.dialing.insert(connection_id, address.clone());
+        tokio::spawn(transport::dial(
+            address,
+            self.identity.clone(),
+            connection_id,
+            self.events_tx.clone(),
+        ));
+    }
+
+    // 节点通过PX或直连配置加入的peer还没有真实连接，先移除再拨号，连接建立后重新加入
+    fn dial_unconnected_peers(&mut self) {
+        let unconnected: Vec<(String, String)> = self
+            .node
+            .peers
+            .iter()
+            .filter(|&(peer_id, _)| !self.connections.contains_key(peer_id))
+            .map(|(peer_id, peer)| (peer_id.clone(), peer.connection_info.clone()))
+            .collect();
+
+        for (peer_id, connection_info) in unconnected {
+            self.node.remove_peer(&peer_id);
+            if connection_info.parse::<SocketAddr>().is_ok() {
+                self.dial(connection_info);
+            } else {
+                debug!(peer = %peer_id, connection_info, "无法拨号的连接信息");
+            }
+        }
+    }
+
+    // 把节点待发送的消息交给对应连接
+    fn flush(&mut self) {
+        while let Ok((peer_id, message)) = self.outbound.try_recv() {
+            match self.connections.get(&peer_id) {
+                Some((_, sender)) => {
+                    // 连接写不过来时丢弃消息，不让单个慢连接拖住运行时
+                    if let Err(TrySendError::Full(_)) = sender.try_send(message) {
+                        debug!(peer = %peer_id, "发送队列已满，丢弃消息");
+                    }
+                }
+                None => trace!(peer = %peer_id, "没有连接，丢弃消息"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn spawn_rejects_invalid_config() {
+        let mut config = NodeConfig {
+            listen_addresses: vec!["127.0.0.1:0".to_string()],
+            ..NodeConfig::default()
+        };
+        config.gossipsub.mesh_low = config.gossipsub.mesh_size + 1;
+
+        let result = NodeHandle::spawn(config).await;
+
+        assert!(matches!(result, Err(GossipSubError::InvalidConfig(_))));
+    }
+}
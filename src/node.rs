@@ -1,63 +1,161 @@
-use crate::message::{self, GossipMessage};
-use crate::types::{GossipSubConfig, MessageType};
-use std::collections::{HashMap, HashSet};
+use crate::clock::{Clock, SystemClock};
+use crate::error::GossipSubError;
+use crate::events::{
+    GossipSubEvent, GraftReason, GraftRejectReason, IWantUnfulfilledReason, PruneReason,
+};
+use crate::message::{GossipMessage, HistoryQuery, PeerInfo};
+use crate::metrics::Metrics;
+use crate::score::PeerScore;
+use crate::store::MessageStore;
+use crate::types::{
+    ConnectionDirection, GossipLimitStats, GossipSubConfig, HistoryRequest, HistorySync,
+    IWantRequest, MessageType, PeerConnection,
+};
+use rand::rngs::StdRng;
+use rand::seq::{IndexedRandom, SliceRandom};
+use rand::{Rng, SeedableRng};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tracing::{debug, debug_span, info, trace, warn};
+use uuid::Uuid;
+
+// 历史消息的时间戳最多允许超前本地时钟的时间(ms)，容忍节点间的时钟偏差
+const HISTORY_CLOCK_SKEW: u64 = 30_000;
+
 // GossipSub节点
 pub struct GossipSubNode {
     pub node_id: String,
-    pub peers: HashMap<String, String>, // peerId -> peer连接信息
+    pub peers: HashMap<String, PeerConnection>, // peerId -> peer连接信息
     pub topics: HashSet<String>,        // 订阅的主题
+    pub peer_topics: HashMap<String, HashSet<String>>, // peerId -> 该peer订阅的主题
     pub mesh: HashMap<String, HashSet<String>>, // topic -> Set(peers)
     pub fanout: HashMap<String, HashSet<String>>, // fanout网络
     pub message_cache: HashMap<String, GossipMessage>, // messageId -> message
-    pub seen_messages: HashSet<String>, // 已见过的消息ID
+    pub message_cached_at: HashMap<String, u64>, // messageId -> 加入缓存的单调时间
+    pub seen_messages: HashMap<String, u64>, // messageId -> 首次见到的单调时间
     pub gossip_history: HashMap<String, Vec<String>>, // topic -> 最近的消息ID列表
-    pub iwant_requests: HashMap<String, u64>, // messageId -> 请求时间戳
+    pub iwant_requests: HashMap<String, IWantRequest>, // messageId -> 进行中的IWANT请求
+    pub iwant_promises: HashMap<String, HashMap<String, u64>>, // peerId -> messageId -> 承诺到期时间戳
     pub graft_backoff: HashMap<String, HashMap<String, u64>>, // topic -> peer -> backoff_until_timestamp
     pub prune_backoff: HashMap<String, HashMap<String, u64>>, // topic -> peer -> backoff_until_timestamp
+    pub peer_scores: HashMap<String, PeerScore>, // peerId -> 评分状态
+    pub ihave_counts: HashMap<String, usize>, // peerId -> 本次心跳周期内收到的IHAVE数量
+    pub iasked_counts: HashMap<String, usize>, // peerId -> 本次心跳周期内通过IWANT请求的消息ID数量
+    pub history_requests: HashMap<String, HistoryRequest>, // 请求消息ID -> 进行中的历史请求
+    pub history_syncs: HashMap<String, HistorySync>, // topic -> 订阅后进行中的历史同步
+    pub history_request_counts: HashMap<String, usize>, // peerId -> 本次心跳周期内收到的历史请求数量
+    pub retransmissions: HashMap<String, HashMap<String, u32>>, // messageId -> peer -> 通过IWANT重发的次数
+    pub gossip_limit_stats: GossipLimitStats, // 超出速率限制而被忽略的统计
+    pub heartbeat_ticks: u64, // 已执行的心跳次数
+    pub metrics: Metrics,     // 协议运行指标
+    event_senders: Vec<UnboundedSender<GossipSubEvent>>, // 协议事件的订阅者
+    outbound: Option<UnboundedSender<(String, GossipMessage)>>, // 待发送消息的出口 (peerId, message)
     pub config: GossipSubConfig,
+    rng: StdRng, // 节点选择使用的随机数生成器
+    message_id_prefix: u64, // 消息ID的高64位，由rng和节点ID派生
+    next_message_seq: u64,  // 消息ID的低64位，每条消息递增
+    clock: Arc<dyn Clock>, // 所有时间相关逻辑使用的时钟
+    store: Option<Box<dyn MessageStore>>, // 持久化的消息历史
 }
 
 impl GossipSubNode {
     pub fn new(node_id: String) -> Self {
-        println!("GossipSub节点 {} 已创建", node_id);
+        Self::with_config(node_id, GossipSubConfig::default())
+    }
+
+    // 使用指定配置创建节点，不会校验配置，调用方需先通过validate或GossipSubConfigBuilder检查
+    pub fn with_config(node_id: String, config: GossipSubConfig) -> Self {
+        Self::with_clock(node_id, config, Arc::new(SystemClock::new()))
+    }
 
-        Self {
+    // 使用指定配置和时钟创建节点，测试和模拟时可注入ManualClock，同样不校验配置
+    pub fn with_clock(node_id: String, config: GossipSubConfig, clock: Arc<dyn Clock>) -> Self {
+        info!(node_id = %node_id, "GossipSub节点已创建");
+
+        // 配置了种子时节点选择可复现，便于测试和模拟
+        let mut rng = match config.rng_seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_os_rng(),
+        };
+        // 消息ID前缀混入节点ID，相同种子的不同节点也不会生成相同的消息ID
+        let mut hasher = DefaultHasher::new();
+        rng.random::<u64>().hash(&mut hasher);
+        node_id.hash(&mut hasher);
+        let message_id_prefix = hasher.finish();
+        let direct_peers = config.direct_peers.clone();
+
+        let mut node = Self {
             node_id,
             peers: HashMap::new(),
             topics: HashSet::new(),
+            peer_topics: HashMap::new(),
             mesh: HashMap::new(),
             fanout: HashMap::new(),
             message_cache: HashMap::new(),
-            seen_messages: HashSet::new(),
+            message_cached_at: HashMap::new(),
+            seen_messages: HashMap::new(),
             gossip_history: HashMap::new(),
             iwant_requests: HashMap::new(),
+            iwant_promises: HashMap::new(),
             graft_backoff: HashMap::new(),
             prune_backoff: HashMap::new(),
-            config: GossipSubConfig::default(),
+            peer_scores: HashMap::new(),
+            ihave_counts: HashMap::new(),
+            iasked_counts: HashMap::new(),
+            history_requests: HashMap::new(),
+            history_syncs: HashMap::new(),
+            history_request_counts: HashMap::new(),
+            retransmissions: HashMap::new(),
+            gossip_limit_stats: GossipLimitStats::default(),
+            heartbeat_ticks: 0,
+            metrics: Metrics::default(),
+            event_senders: Vec::new(),
+            outbound: None,
+            config,
+            rng,
+            message_id_prefix,
+            next_message_seq: 0,
+            clock,
+            store: None,
+        };
+
+        // 启动时连接所有直连节点
+        for (peer_id, connection_info) in direct_peers {
+            node.add_peer(peer_id, connection_info);
         }
+        node
     }
 
     // 发布消息到指定主题
-    pub fn publish(&mut self, topic: &str, content: Vec<u8>) -> Result<String, String> {
+    pub fn publish(&mut self, topic: &str, content: Vec<u8>) -> Result<String, GossipSubError> {
         if !self.topics.contains(topic) {
-            return Err(format!("节点 {} 未订阅主题 {}", self.node_id, topic));
+            return Err(GossipSubError::NotSubscribed(topic.to_string()));
         }
 
-        let message = GossipMessage::new(MessageType::Publish)
+        let message = self.new_message(MessageType::Publish)
             .with_topic(topic.to_string())
             .with_content(content)
             .with_from(self.node_id.clone());
         let message_id = message.message_id.clone();
 
+        // 拒绝超过传输大小上限的消息
+        let size = message.encoded_len();
+        if size > self.config.max_transmit_size {
+            return Err(GossipSubError::MessageTooLarge {
+                size,
+                max_size: self.config.max_transmit_size,
+            });
+        }
+
         // 缓存消息
-        self.message_cache
-            .insert(message_id.clone(), message.clone());
-        self.seen_messages.insert(message_id.clone());
+        self.cache_message(message.clone());
+        self.seen_messages.insert(message_id.clone(), self.clock.now_millis());
 
-        println!(
-            "节点 {} 发布消息到主题 {}: ID={}",
-            self.node_id, topic, message_id
-        );
+        debug!(node_id = %self.node_id, topic, message_id = %message_id, "发布消息");
 
         // 转发消息给mesh中的节点
         self.forward_to_mesh(topic, &message)?;
@@ -69,6 +167,7 @@ impl GossipSubNode {
 
         // 添加到gossip历史中
         self.add_to_gossip_history(topic, &message_id);
+        self.metrics.record_published(topic);
         Ok(message_id)
     }
 
@@ -77,7 +176,7 @@ impl GossipSubNode {
         let history = self
             .gossip_history
             .entry(topic.to_string())
-            .or_insert(Vec::new());
+            .or_default();
         history.push(message_id.to_string());
 
         // 保持历史记录在合理大小内
@@ -86,23 +185,91 @@ impl GossipSubNode {
         }
     }
 
-    // 执行gossip心跳 - 发送IHAVE消息
-    pub fn gossip_heartbeat(&mut self) -> Result<(), String> {
-        println!("节点 {} 执行gossip心跳", self.node_id);
+    // 执行gossip心跳 - 维护mesh并发送IHAVE消息
+    pub fn gossip_heartbeat(&mut self) -> Result<(), GossipSubError> {
+        self.heartbeat_ticks += 1;
+        let _span = debug_span!(
+            "heartbeat",
+            node_id = %self.node_id,
+            tick = self.heartbeat_ticks
+        )
+        .entered();
+        trace!("执行gossip心跳");
+
+        let started = Instant::now();
+        let result = self.run_heartbeat();
+        self.metrics.observe_heartbeat(started.elapsed());
+        result
+    }
+
+    fn run_heartbeat(&mut self) -> Result<(), GossipSubError> {
+        // 衰减评分并重置每个心跳周期的计数
+        self.decay_peer_scores();
+        self.ihave_counts.clear();
+        self.iasked_counts.clear();
+        self.history_request_counts.clear();
+
+        // 清理过期的消息缓存、已见消息ID和退避记录
+        self.cleanup_message_cache();
+        self.cleanup_backoffs();
+
+        // 维护mesh大小
+        for topic in self.sorted_topics() {
+            self.maintain_mesh(&topic)?;
+        }
+
+        // 定期重连断开的直连节点
+        if self
+            .heartbeat_ticks
+            .is_multiple_of(self.config.direct_connect_ticks)
+        {
+            self.reconnect_direct_peers();
+        }
+
+        // 定期检查mesh质量，必要时机会性地GRAFT高分节点
+        if self
+            .heartbeat_ticks
+            .is_multiple_of(self.config.opportunistic_graft_ticks)
+        {
+            for topic in self.sorted_topics() {
+                self.opportunistic_graft(&topic)?;
+            }
+        }
 
-        // 发送IHAVE消息
-        for topic in self.topics.clone() {
+        // 向订阅主题和fanout主题发送IHAVE消息
+        let mut gossip_topics: Vec<String> = self
+            .topics
+            .iter()
+            .chain(self.fanout.keys())
+            .cloned()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        gossip_topics.sort();
+        for topic in gossip_topics {
             self.send_ihave_messages(&topic)?;
         }
 
+        // 惩罚未兑现IWANT承诺的节点
+        self.penalize_broken_promises();
+
+        // 超时的IWANT请求换其他宣告者重试
+        self.retry_iwant_requests()?;
+
         // 清理过期的IWANT请求
         self.cleanup_expired_iwant_requests();
 
+        // 处理超时的历史请求，继续未完成的历史同步
+        self.maintain_history_syncs()?;
+
+        // 按保留策略清理历史消息
+        self.apply_store_retention();
+
         Ok(())
     }
 
     // 维护mesh网络 - 检查mesh大小并进行调整
-    fn maintain_mesh(&mut self, topic: &str) -> Result<(), String> {
+    fn maintain_mesh(&mut self, topic: &str) -> Result<(), GossipSubError> {
         let mesh_size = self.get_mesh_size(topic);
 
         // 如果mesh太小，尝试添加节点
@@ -111,81 +278,221 @@ impl GossipSubNode {
         }
         // 如果mesh太大，移除一些节点
         else if mesh_size > self.config.mesh_high {
-            self.contract_mesh(&topic)?;
+            self.contract_mesh(topic)?;
+        }
+
+        // 出站连接不足时补充出站节点，防止mesh被入站连接占满
+        self.ensure_outbound_quota(topic)?;
+
+        Ok(())
+    }
+
+    // 保证mesh中的出站连接数不低于mesh_outbound_min
+    fn ensure_outbound_quota(&mut self, topic: &str) -> Result<(), GossipSubError> {
+        let outbound_count = self.outbound_mesh_count(topic);
+        if outbound_count >= self.config.mesh_outbound_min {
+            return Ok(());
         }
 
+        let needed = self.config.mesh_outbound_min - outbound_count;
+        let candidates: Vec<String> = self
+            .peers
+            .iter()
+            .filter(|&(peer_id, peer)| {
+                peer.direction == ConnectionDirection::Outbound
+                    && !self.is_in_mesh(topic, peer_id)
+                    && !self.is_direct_peer(peer_id)
+                    && !self.is_peer_in_backoff(topic, peer_id, true)
+            })
+            .map(|(peer_id, _)| peer_id.clone())
+            .collect();
+        let candidates = self.random_peers(candidates, needed);
+
+        for peer_id in candidates {
+            self.graft_peer(topic, &peer_id, GraftReason::OutboundQuota)?;
+        }
         Ok(())
     }
 
     // 扩展mesh - 发送GRAFT消息
-    pub fn expand_mesh(&mut self, topic: &str) -> Result<(), String> {
+    pub fn expand_mesh(&mut self, topic: &str) -> Result<(), GossipSubError> {
         let current_mesh = self.mesh.get(topic).cloned().unwrap_or_default();
-        let needed = self.config.mesh_size - current_mesh.len();
+        let needed = self.config.mesh_size.saturating_sub(current_mesh.len());
 
         if needed == 0 {
             return Ok(());
         }
 
-        // 找到可以加入mesh的候选节点
+        // 找到可以加入mesh的候选节点，从中随机选择
         let candidates: Vec<String> = self
             .peers
             .keys()
             .filter(|&peer_id| {
-                !current_mesh.contains(peer_id) && !self.is_peer_in_backoff(topic, peer_id, true) // 检查GRAFT退避
+                !current_mesh.contains(peer_id)
+                    && !self.is_direct_peer(peer_id)
+                    && !self.is_peer_in_backoff(topic, peer_id, true) // 检查GRAFT退避
             })
-            .take(needed)
             .cloned()
             .collect();
+        let candidates = self.random_peers(candidates, needed);
 
         for peer_id in candidates {
-            // 发送GRAFT消息
-            let graft_message = GossipMessage::new(MessageType::Graft)
-                .with_topic(topic.to_string())
-                .with_from(self.node_id.clone())
-                .with_to(peer_id.clone());
+            self.graft_peer(topic, &peer_id, GraftReason::MeshMaintenance)?;
+        }
+        Ok(())
+    }
+
+    // 机会性GRAFT - mesh评分中位数过低时，加入评分高于中位数的节点
+    fn opportunistic_graft(&mut self, topic: &str) -> Result<(), GossipSubError> {
+        let current_mesh = self.mesh.get(topic).cloned().unwrap_or_default();
+        if current_mesh.len() <= 1 {
+            return Ok(());
+        }
+
+        let mut mesh_scores: Vec<f64> = current_mesh
+            .iter()
+            .map(|peer_id| self.peer_score(peer_id))
+            .collect();
+        mesh_scores.sort_by(|a, b| a.total_cmp(b));
+        let median = mesh_scores[mesh_scores.len() / 2];
 
-            self.send_message_to_peer(&peer_id, &graft_message)?;
+        if median >= self.config.score_thresholds.opportunistic_graft_threshold {
+            return Ok(());
+        }
 
-            // 将节点添加到mesh中
-            self.mesh
-                .entry(topic.to_string())
-                .or_insert_with(HashSet::new)
-                .insert(peer_id.clone());
+        let candidates: Vec<String> = self
+            .peers
+            .keys()
+            .filter(|&peer_id| {
+                !current_mesh.contains(peer_id)
+                    && !self.is_direct_peer(peer_id)
+                    && !self.is_peer_in_backoff(topic, peer_id, true)
+                    && self.peer_score(peer_id) > median
+            })
+            .cloned()
+            .collect();
+        let candidates = self.random_peers(candidates, self.config.opportunistic_graft_peers);
 
-            println!(
-                "节点 {} 向 {} 发送GRAFT请求，加入主题 {} 的mesh",
-                self.node_id, peer_id, topic
+        if !candidates.is_empty() {
+            debug!(
+                node_id = %self.node_id,
+                topic,
+                median,
+                count = candidates.len(),
+                "mesh评分中位数过低，机会性GRAFT"
             );
         }
+
+        for peer_id in candidates {
+            self.graft_peer(topic, &peer_id, GraftReason::Opportunistic)?;
+        }
+        Ok(())
+    }
+
+    // 向节点发送GRAFT并将其加入mesh
+    fn graft_peer(
+        &mut self,
+        topic: &str,
+        peer_id: &str,
+        reason: GraftReason,
+    ) -> Result<(), GossipSubError> {
+        let graft_message = self.new_message(MessageType::Graft)
+            .with_topic(topic.to_string())
+            .with_from(self.node_id.clone())
+            .with_to(peer_id.to_string());
+
+        self.send_message_to_peer(peer_id, &graft_message)?;
+
+        // 将节点添加到mesh中
+        self.mesh
+            .entry(topic.to_string())
+            .or_default()
+            .insert(peer_id.to_string());
+
+        debug!(node_id = %self.node_id, peer = peer_id, topic, ?reason, "发送GRAFT，加入mesh");
+        self.emit(GossipSubEvent::Grafted {
+            peer_id: peer_id.to_string(),
+            topic: topic.to_string(),
+            direction: ConnectionDirection::Outbound,
+            reason,
+        });
         Ok(())
     }
 
     // 收缩mesh - 发送PRUNE消息
-    pub fn contract_mesh(&mut self, topic: &str) -> Result<(), String> {
-        let mesh_peers = self.mesh.get(topic).cloned().unwrap_or_default();
-        let to_remove = mesh_peers.len() - self.config.mesh_size;
+    pub fn contract_mesh(&mut self, topic: &str) -> Result<(), GossipSubError> {
+        let mesh_size = self.config.mesh_size;
+        let mesh_peers: Vec<String> = self
+            .mesh
+            .get(topic)
+            .map(|peers| peers.iter().cloned().collect())
+            .unwrap_or_default();
 
-        if to_remove == 0 {
+        if mesh_peers.len() <= mesh_size {
             return Ok(());
         }
 
-        // 随机选择要移除的节点
-        let peers_to_prune: Vec<String> = mesh_peers.iter().take(to_remove).cloned().collect();
+        // 先打乱顺序，再按评分从高到低稳定排序，保留评分最高的mesh_retain_score个节点
+        let count = mesh_peers.len();
+        let mut mesh_peers = self.random_peers(mesh_peers, count);
+        mesh_peers.sort_by(|a, b| self.peer_score(b).total_cmp(&self.peer_score(a)));
+        let retain = std::cmp::min(self.config.mesh_retain_score, mesh_size);
+        let mut peers_to_prune = mesh_peers.split_off(retain);
+        let mut kept = mesh_peers;
+
+        // 剩余名额从其他节点中随机补足
+        peers_to_prune.shuffle(&mut self.rng);
+        kept.extend(peers_to_prune.drain(..mesh_size - retain));
+
+        // 出站节点不足mesh_outbound_min时，用被移除的出站节点替换非保留的入站节点
+        let mut outbound_count = kept.iter().filter(|peer_id| self.is_outbound(peer_id)).count();
+        while outbound_count < self.config.mesh_outbound_min {
+            let Some(outbound_pos) = peers_to_prune
+                .iter()
+                .position(|peer_id| self.is_outbound(peer_id))
+            else {
+                break;
+            };
+            let Some(inbound_pos) = kept[retain..]
+                .iter()
+                .rposition(|peer_id| !self.is_outbound(peer_id))
+            else {
+                break;
+            };
+
+            let outbound_peer = peers_to_prune.remove(outbound_pos);
+            let inbound_peer = std::mem::replace(&mut kept[retain + inbound_pos], outbound_peer);
+            peers_to_prune.push(inbound_peer);
+            outbound_count += 1;
+        }
 
         for peer_id in peers_to_prune {
-            self.prune_peer_from_mesh(topic, &peer_id)?;
+            self.prune_peer_from_mesh(
+                topic,
+                &peer_id,
+                self.config.prune_backoff,
+                PruneReason::MeshOverflow,
+            )?;
         }
 
         Ok(())
     }
 
     // 从mesh中剪除节点
-    fn prune_peer_from_mesh(&mut self, topic: &str, peer_id: &str) -> Result<(), String> {
-        // 发送PRUNE消息
-        let prune_message = GossipMessage::new(MessageType::Prune)
+    fn prune_peer_from_mesh(
+        &mut self,
+        topic: &str,
+        peer_id: &str,
+        backoff: u64,
+        reason: PruneReason,
+    ) -> Result<(), GossipSubError> {
+        // 发送PRUNE消息，附带退避时间和其他mesh节点供对方连接
+        let prune_message = self.new_message(MessageType::Prune)
             .with_topic(topic.to_string())
             .with_from(self.node_id.clone())
-            .with_to(peer_id.to_string());
+            .with_to(peer_id.to_string())
+            .with_peers(self.px_peers(topic, peer_id))
+            .with_backoff(backoff);
 
         self.send_message_to_peer(peer_id, &prune_message)?;
 
@@ -195,23 +502,60 @@ impl GossipSubNode {
         }
 
         // 设置PRUNE退避
-        let backoff_until = GossipMessage::current_timestamp() + self.config.prune_backoff;
+        let backoff_until = self.clock.now_millis().saturating_add(backoff);
         self.prune_backoff
             .entry(topic.to_string())
-            .or_insert_with(HashMap::new)
+            .or_default()
             .insert(peer_id.to_string(), backoff_until);
 
-        println!(
-            "节点 {} 向 {} 发送PRUNE消息，从主题 {} 的mesh中移除",
-            self.node_id, peer_id, topic
+        debug!(
+            node_id = %self.node_id,
+            peer = peer_id,
+            topic,
+            backoff,
+            ?reason,
+            "发送PRUNE，从mesh中移除"
         );
+        self.emit(GossipSubEvent::Pruned {
+            peer_id: peer_id.to_string(),
+            topic: topic.to_string(),
+            direction: ConnectionDirection::Outbound,
+            reason,
+        });
 
         Ok(())
     }
 
+    // 选择PRUNE中附带的PX节点：该主题mesh中除被剪除节点外的其他节点
+    fn px_peers(&mut self, topic: &str, pruned_peer: &str) -> Vec<PeerInfo> {
+        if !self.config.do_px {
+            return Vec::new();
+        }
+
+        // 先排序再随机挑选，保证相同种子下结果可复现
+        let mut candidates: Vec<&String> = self
+            .mesh
+            .get(topic)
+            .into_iter()
+            .flatten()
+            .filter(|&peer_id| peer_id != pruned_peer)
+            .collect();
+        candidates.sort();
+        candidates
+            .choose_multiple(&mut self.rng, self.config.prune_peers)
+            .map(|&peer_id| PeerInfo {
+                peer_id: peer_id.clone(),
+                signed_peer_record: self
+                    .peers
+                    .get(peer_id)
+                    .map(|peer| peer.connection_info.clone().into_bytes()),
+            })
+            .collect()
+    }
+
     // 检查节点是否在退避期
     fn is_peer_in_backoff(&self, topic: &str, peer_id: &str, is_graft: bool) -> bool {
-        let current_time = GossipMessage::current_timestamp();
+        let current_time = self.clock.now_millis();
 
         let backoff_map = if is_graft {
             &self.graft_backoff
@@ -219,23 +563,20 @@ impl GossipSubNode {
             &self.prune_backoff
         };
 
-        if let Some(topic_backoffs) = backoff_map.get(topic) {
-            if let Some(&backoff_until) = topic_backoffs.get(peer_id) {
-                return current_time < backoff_until;
-            }
+        if let Some(topic_backoffs) = backoff_map.get(topic)
+            && let Some(&backoff_until) = topic_backoffs.get(peer_id)
+        {
+            return current_time < backoff_until;
         }
         false
     }
 
     // 向非mesh节点发送IHAVE消息
-    fn send_ihave_messages(&mut self, topic: &str) -> Result<(), String> {
+    fn send_ihave_messages(&mut self, topic: &str) -> Result<(), GossipSubError> {
         // 获取该主题最近的消息id
         let recent_messages = if let Some(history) = self.gossip_history.get(topic) {
-            let start = if history.len() > self.config.gossip_size {
-                history.len() - self.config.gossip_size
-            } else {
-                0
-            };
+            let count = std::cmp::min(self.config.gossip_size, self.config.max_ihave_length);
+            let start = history.len().saturating_sub(count);
             history[start..].to_vec()
         } else {
             Vec::new()
@@ -245,55 +586,92 @@ impl GossipSubNode {
             return Ok(());
         }
 
-        // 选择要发送的IHAVE消息的节点（非mesh节点）
-        let target_peers: Vec<String> = self
+        // 符合条件的节点：订阅了该主题、不在mesh和fanout中、不是直连节点、评分不低于gossip阈值
+        let eligible_peers: Vec<String> = self
             .peers
             .keys()
-            .filter(|&peer_id| !self.is_in_mesh(topic, peer_id))
-            .take(self.config.gossip_size)
+            .filter(|&peer_id| {
+                self.peer_topics
+                    .get(peer_id)
+                    .is_some_and(|topics| topics.contains(topic))
+                    && !self.is_in_mesh(topic, peer_id)
+                    && !self
+                        .fanout
+                        .get(topic)
+                        .is_some_and(|peers| peers.contains(peer_id))
+                    && !self.is_direct_peer(peer_id)
+                    && self.peer_score(peer_id) >= self.config.score_thresholds.gossip_threshold
+            })
             .cloned()
             .collect();
 
+        // 随机选择 max(d_lazy, gossip_factor * 符合条件节点数) 个节点
+        let target_count = std::cmp::max(
+            self.config.d_lazy,
+            (self.config.gossip_factor * eligible_peers.len() as f64) as usize,
+        );
+        let target_peers = self.random_peers(eligible_peers, target_count);
+
         for peer_id in &target_peers {
-            let ihave_message = GossipMessage::new(MessageType::IHave)
+            let ihave_message = self.new_message(MessageType::IHave)
                 .with_topic(topic.to_string())
                 .with_from(self.node_id.clone())
                 .with_to(peer_id.clone())
                 .with_message_ids(recent_messages.clone());
 
-            self.send_message_to_peer(&peer_id, &ihave_message)?;
+            self.send_message_to_peer(peer_id, &ihave_message)?;
         }
 
-        if !recent_messages.is_empty() {
-            println!(
-                "节点 {} 向 {} 个节点发送了IHAVE消息，包含 {} 个消息ID",
-                self.node_id,
-                target_peers.len(),
-                recent_messages.len()
-            );
-        }
+        trace!(
+            node_id = %self.node_id,
+            topic,
+            peers = target_peers.len(),
+            message_ids = recent_messages.len(),
+            "发送IHAVE"
+        );
 
         Ok(())
     }
 
     // 转发消息给mesh网络中的节点
-    fn forward_to_mesh(&self, topic: &str, message: &GossipMessage) -> Result<(), String> {
+    fn forward_to_mesh(&self, topic: &str, message: &GossipMessage) -> Result<(), GossipSubError> {
         if let Some(mesh_peers) = self.mesh.get(topic) {
             for peer_id in mesh_peers {
                 self.send_message_to_peer(peer_id, message)?;
             }
-            println!(
-                "节点 {} 向mesh中的 {} 个节点转发了消息",
-                self.node_id,
-                mesh_peers.len()
+            trace!(
+                node_id = %self.node_id,
+                topic,
+                message_id = %message.message_id,
+                peers = mesh_peers.len(),
+                "向mesh转发消息"
             );
         }
 
+        // 直连节点始终接收消息
+        self.forward_to_direct_peers(message, None)
+    }
+
+    // 转发消息给所有已连接的直连节点（除了发送者和消息源）
+    fn forward_to_direct_peers(
+        &self,
+        message: &GossipMessage,
+        from_peer: Option<&str>,
+    ) -> Result<(), GossipSubError> {
+        for peer_id in self.config.direct_peers.keys() {
+            if !self.peers.contains_key(peer_id)
+                || Some(peer_id.as_str()) == from_peer
+                || message.from.as_ref() == Some(peer_id)
+            {
+                continue;
+            }
+            self.send_message_to_peer(peer_id, message)?;
+        }
         Ok(())
     }
 
     // 转发消息给fanout网络中的节点
-    fn forward_to_fanout(&mut self, topic: &str, message: &GossipMessage) -> Result<(), String> {
+    fn forward_to_fanout(&mut self, topic: &str, message: &GossipMessage) -> Result<(), GossipSubError> {
         // 如果fanout不存在创造一个
         if !self.fanout.contains_key(topic) {
             self.fanout.insert(topic.to_string(), HashSet::new());
@@ -302,19 +680,17 @@ impl GossipSubNode {
             let available_peers: Vec<String> = self
                 .peers
                 .keys()
-                .filter(|&peer_id| !self.is_in_mesh(topic, peer_id))
+                .filter(|&peer_id| {
+                    !self.is_in_mesh(topic, peer_id)
+                        && !self.is_direct_peer(peer_id)
+                        && self.peer_score(peer_id) >= self.config.score_thresholds.publish_threshold
+                })
                 .cloned()
                 .collect();
+            let selected = self.random_peers(available_peers, self.config.gossip_size);
 
             let fanout_peers = self.fanout.get_mut(topic).unwrap();
-            let needed = std::cmp::min(self.config.gossip_size, available_peers.len());
-
-            for (i, peer_id) in available_peers.iter().enumerate() {
-                if i >= needed {
-                    break;
-                }
-                fanout_peers.insert(peer_id.clone());
-            }
+            fanout_peers.extend(selected);
         }
 
         // 转发消息给fanout节点
@@ -322,10 +698,12 @@ impl GossipSubNode {
             for peer_id in fanout_peers {
                 self.send_message_to_peer(peer_id, message)?;
             }
-            println!(
-                "节点 {} 向fanout中的 {} 个节点转发了消息",
-                self.node_id,
-                fanout_peers.len()
+            trace!(
+                node_id = %self.node_id,
+                topic,
+                message_id = %message.message_id,
+                peers = fanout_peers.len(),
+                "向fanout转发消息"
             );
         }
 
@@ -333,32 +711,150 @@ impl GossipSubNode {
     }
 
     // 发送消息给指定的对等节点
-    fn send_message_to_peer(&self, peer_id: &str, message: &GossipMessage) -> Result<(), String> {
-        // 这里模拟发送消息的过程
-        // 在实际实现中，这里会通过网络发送消息
-        println!(
-            "  {} -> {}: 发送 {:?} 消息 (ID: {})",
-            self.node_id, peer_id, message.message_type, message.message_id
+    fn send_message_to_peer(&self, peer_id: &str, message: &GossipMessage) -> Result<(), GossipSubError> {
+        // 消息交给outbound_stream的接收方投递，没有接收方时直接丢弃
+        let Some(outbound) = self.outbound.as_ref() else {
+            trace!(node_id = %self.node_id, peer = peer_id, "没有发送队列，丢弃消息");
+            return Ok(());
+        };
+        trace!(
+            node_id = %self.node_id,
+            peer = peer_id,
+            message_type = ?message.message_type,
+            message_id = %message.message_id,
+            "发送消息"
         );
+        outbound
+            .send((peer_id.to_string(), message.clone()))
+            .map_err(|_| GossipSubError::Transport("发送队列已关闭".to_string()))?;
+        self.metrics.record_sent(&message.message_type);
         Ok(())
     }
 
+    // 获取待发送消息的接收端，由模拟器或传输层取出后投递，重复调用会替换之前的接收端
+    pub fn outbound_stream(&mut self) -> UnboundedReceiver<(String, GossipMessage)> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.outbound = Some(sender);
+        receiver
+    }
+
+    // 创建消息，时间戳取自节点时钟
+    // 消息ID由前缀和序号组成，配置相同种子时可复现
+    fn new_message(&mut self, message_type: MessageType) -> GossipMessage {
+        let message_id = Uuid::from_u64_pair(self.message_id_prefix, self.next_message_seq);
+        self.next_message_seq += 1;
+        GossipMessage::new(message_type)
+            .with_message_id(message_id.to_string())
+            .with_timestamp(self.clock.unix_millis())
+    }
+
+    // 缓存消息并记录加入缓存的时间，配置了消息存储时同时写入历史
+    fn cache_message(&mut self, message: GossipMessage) {
+        if let Some(store) = self.store.as_mut()
+            && let Err(e) = store.append(&message)
+        {
+            warn!(node_id = %self.node_id, message_id = %message.message_id, error = %e, "写入消息存储失败");
+        }
+        self.message_cached_at
+            .insert(message.message_id.clone(), self.clock.now_millis());
+        self.message_cache.insert(message.message_id.clone(), message);
+    }
+
+    // 设置消息存储，之后接受的发布消息都会写入其中
+    pub fn set_message_store(&mut self, store: Box<dyn MessageStore>) {
+        self.store = Some(store);
+    }
+
+    pub fn message_store(&self) -> Option<&dyn MessageStore> {
+        self.store.as_deref()
+    }
+
+    // 按保留策略清理消息存储
+    fn apply_store_retention(&mut self) {
+        let now = self.clock.unix_millis();
+        let Some(store) = self.store.as_mut() else {
+            return;
+        };
+        match store.apply_retention(now) {
+            Ok(0) => {}
+            Ok(removed) => debug!(node_id = %self.node_id, removed, "清理过期的历史消息"),
+            Err(e) => warn!(node_id = %self.node_id, error = %e, "清理消息存储失败"),
+        }
+    }
+
     // 接受并处理消息
     pub fn handle_message(
         &mut self,
         message: GossipMessage,
         from_peer: &str,
-    ) -> Result<(), String> {
+    ) -> Result<(), GossipSubError> {
+        let _span = debug_span!(
+            "handle_message",
+            node_id = %self.node_id,
+            peer = from_peer,
+            message_type = ?message.message_type,
+            message_id = %message.message_id
+        )
+        .entered();
+
+        // 评分低于灰名单阈值的peer发来的任何消息都直接忽略
+        if self.peer_score(from_peer) < self.config.score_thresholds.graylist_threshold {
+            trace!("忽略灰名单节点的消息");
+            return Ok(());
+        }
+
+        // 丢弃超过传输大小上限的消息，并惩罚发送者
+        let size = message.encoded_len();
+        if size > self.config.max_transmit_size {
+            warn!(
+                size,
+                max_size = self.config.max_transmit_size,
+                "丢弃超大消息"
+            );
+            self.add_peer_penalty(from_peer, 1.0);
+            self.metrics.invalid += 1;
+            let error = GossipSubError::MessageTooLarge {
+                size,
+                max_size: self.config.max_transmit_size,
+            };
+            self.emit_invalid(&message, from_peer, &error);
+            return Err(error);
+        }
+
+        // 所有控制消息和发布消息都必须指定主题
+        if message.topic.is_none() {
+            warn!("丢弃无主题消息");
+            self.add_peer_penalty(from_peer, 1.0);
+            self.metrics.invalid += 1;
+            let error = GossipSubError::ValidationFailed(format!(
+                "{:?} 消息 {} 缺少主题",
+                message.message_type, message.message_id
+            ));
+            self.emit_invalid(&message, from_peer, &error);
+            return Err(error);
+        }
+
+        // 收到消息即兑现所有节点对它的IWANT承诺（包括重复消息）
+        if message.message_type == MessageType::Publish {
+            self.fulfill_iwant_promises(&message.message_id);
+            self.iwant_requests.remove(&message.message_id);
+        }
+
         // 检查是否已经见过这个消息
-        if self.seen_messages.contains(&message.message_id) {
+        if self.seen_messages.contains_key(&message.message_id) {
+            self.metrics.duplicates += 1;
+            self.emit(GossipSubEvent::MessageDuplicate {
+                message_id: message.message_id.clone(),
+                peer_id: from_peer.to_string(),
+            });
             return Ok(());
         }
 
-        self.seen_messages.insert(message.message_id.clone());
-        println!(
-            "节点 {} 从 {} 接收到消息: {:?} (ID: {})",
-            self.node_id, from_peer, message.message_type, message.message_id
-        );
+        self.seen_messages
+            .insert(message.message_id.clone(), self.clock.now_millis());
+        self.metrics
+            .record_received(&message.message_type, message.topic.as_deref());
+        trace!("接收到消息");
 
         match message.message_type {
             MessageType::Publish => self.handle_publish_message(message, from_peer),
@@ -366,6 +862,10 @@ impl GossipSubNode {
             MessageType::IWant => self.handle_iwant_message(message, from_peer),
             MessageType::Graft => self.handle_graft_message(message, from_peer),
             MessageType::Prune => self.handle_prune_message(message, from_peer),
+            MessageType::Subscribe => self.handle_subscribe_message(message, from_peer),
+            MessageType::Unsubscribe => self.handle_unsubscribe_message(message, from_peer),
+            MessageType::HistoryRequest => self.handle_history_request(message, from_peer),
+            MessageType::HistoryResponse => self.handle_history_response(message, from_peer),
         }
     }
 
@@ -374,7 +874,7 @@ impl GossipSubNode {
         &mut self,
         message: GossipMessage,
         from_peer: &str,
-    ) -> Result<(), String> {
+    ) -> Result<(), GossipSubError> {
         if let Some(topic) = &message.topic {
             // 只处理我们订阅的主题
             if !self.topics.contains(topic) {
@@ -382,18 +882,20 @@ impl GossipSubNode {
             }
 
             // 缓存消息
-            self.message_cache
-                .insert(message.message_id.clone(), message.clone());
-
-            println!(
-                "节点 {} 处理发布消息: 主题={}, 内容={:?}",
-                self.node_id,
-                topic,
-                message
-                    .content
-                    .as_ref()
-                    .map(|c| String::from_utf8_lossy(c))
-                    .unwrap_or_default()
+            self.cache_message(message.clone());
+            self.emit(GossipSubEvent::MessageReceived {
+                message: message.clone(),
+                propagation_source: from_peer.to_string(),
+            });
+
+            // 加入gossip历史，以便通过IHAVE告知其他节点
+            self.add_to_gossip_history(topic, &message.message_id);
+
+            debug!(
+                topic = %topic,
+                from = ?message.from,
+                size = message.content.as_ref().map_or(0, |c| c.len()),
+                "处理发布消息"
             );
 
             // 转发给mesh中的其他节点（除了发送者）
@@ -404,6 +906,9 @@ impl GossipSubNode {
                     }
                 }
             }
+
+            // 直连节点始终接收消息
+            self.forward_to_direct_peers(&message, Some(from_peer))?;
         }
         Ok(())
     }
@@ -413,47 +918,151 @@ impl GossipSubNode {
         &mut self,
         message: GossipMessage,
         from_peer: &str,
-    ) -> Result<(), String> {
+    ) -> Result<(), GossipSubError> {
         if let Some(topic) = &message.topic {
             if !self.topics.contains(topic) {
                 return Ok(());
             }
 
-            // 检查我们想要哪些消息
+            // 每个心跳周期内只接受有限数量的IHAVE
+            let ihave_count = self.ihave_counts.entry(from_peer.to_string()).or_default();
+            *ihave_count += 1;
+            if *ihave_count > self.config.max_ihave_messages {
+                self.gossip_limit_stats.ignored_ihave += 1;
+                debug!(count = *ihave_count, "本周期IHAVE已达上限，忽略");
+                return Ok(());
+            }
+
+            // 检查我们想要哪些消息（最多处理max_ihave_length个ID）
             let mut wanted_messages = Vec::new();
-            for message_id in &message.message_ids {
+            for message_id in message.message_ids.iter().take(self.config.max_ihave_length) {
                 // 如果我们没有这个消息，且不在我们的缓存中，我们就想要它
-                if !self.seen_messages.contains(message_id)
-                    && !self.message_cache.contains_key(message_id)
+                if self.seen_messages.contains_key(message_id)
+                    || self.message_cache.contains_key(message_id)
+                {
+                    continue;
+                }
+
+                // 已经在向足够多的peer请求时，只记下该宣告者供超时后重试
+                if let Some(request) = self.iwant_requests.get_mut(message_id)
+                    && (request.in_flight.contains_key(from_peer)
+                        || request.in_flight.len() >= self.config.max_iwant_in_flight)
                 {
-                    wanted_messages.push(message_id.clone());
+                    if !request.in_flight.contains_key(from_peer)
+                        && !request.advertisers.iter().any(|peer| peer == from_peer)
+                    {
+                        request.advertisers.push(from_peer.to_string());
+                    }
+                    continue;
                 }
+
+                wanted_messages.push(message_id.clone());
+            }
+
+            // 每个心跳周期内向单个peer请求的消息ID总数有上限
+            let iasked = self.iasked_counts.entry(from_peer.to_string()).or_default();
+            let remaining = self.config.max_iwant_ids.saturating_sub(*iasked);
+            if wanted_messages.len() > remaining {
+                let ignored = wanted_messages.len() - remaining;
+                self.gossip_limit_stats.ignored_iwant_ids += ignored as u64;
+                wanted_messages.truncate(remaining);
+                debug!(ignored, "本周期IWANT请求已达上限，忽略部分消息ID");
             }
+            *iasked += wanted_messages.len();
 
             if !wanted_messages.is_empty() {
-                println!(
-                    "节点 {} 从 {} 收到IHAVE消息，想要 {} 个消息",
-                    self.node_id,
-                    from_peer,
-                    wanted_messages.len()
-                );
+                trace!(wanted = wanted_messages.len(), "IHAVE中有需要的消息");
 
-                // 记录IWANT请求时间
-                let current_time = GossipMessage::current_timestamp();
-                for message_id in &wanted_messages {
-                    self.iwant_requests.insert(message_id.clone(), current_time);
-                }
+                self.send_iwant(from_peer, topic, wanted_messages)?;
+            }
+        }
+        Ok(())
+    }
 
-                // 发送IWANT消息
-                let iwant_message = GossipMessage::new(MessageType::IWant)
-                    .with_topic(topic.clone())
-                    .with_from(self.node_id.clone())
-                    .with_to(from_peer.to_string())
-                    .with_message_ids(wanted_messages);
+    // 向peer发送IWANT，并记录进行中的请求和对方的承诺
+    fn send_iwant(
+        &mut self,
+        peer_id: &str,
+        topic: &str,
+        message_ids: Vec<String>,
+    ) -> Result<(), GossipSubError> {
+        let current_time = self.clock.now_millis();
+        for message_id in &message_ids {
+            let request = self
+                .iwant_requests
+                .entry(message_id.clone())
+                .or_insert_with(|| IWantRequest {
+                    topic: topic.to_string(),
+                    first_requested: current_time,
+                    in_flight: HashMap::new(),
+                    advertisers: Vec::new(),
+                });
+            request.in_flight.insert(peer_id.to_string(), current_time);
+            request.advertisers.retain(|peer| peer != peer_id);
+        }
+
+        // 记录对方的承诺：应在iwant_followup_time内送达这些消息
+        let expires_at = current_time + self.config.iwant_followup_time;
+        let promises = self.iwant_promises.entry(peer_id.to_string()).or_default();
+        for message_id in &message_ids {
+            promises.entry(message_id.clone()).or_insert(expires_at);
+        }
+
+        // 发送IWANT消息
+        let iwant_message = self.new_message(MessageType::IWant)
+            .with_topic(topic.to_string())
+            .with_from(self.node_id.clone())
+            .with_to(peer_id.to_string())
+            .with_message_ids(message_ids);
 
-                self.send_message_to_peer(from_peer, &iwant_message)?;
+        self.send_message_to_peer(peer_id, &iwant_message)
+    }
+
+    // 对超时未送达的IWANT请求，换其他宣告过该消息的peer重试
+    fn retry_iwant_requests(&mut self) -> Result<(), GossipSubError> {
+        let current_time = self.clock.now_millis();
+        let followup_time = self.config.iwant_followup_time;
+        let max_in_flight = self.config.max_iwant_in_flight;
+        let max_iwant_ids = self.config.max_iwant_ids;
+        let iasked_counts = &mut self.iasked_counts;
+
+        // 按消息ID顺序分配重试额度，(peer, topic) -> 需要重试的消息ID，按peer排序发送
+        let mut requests: Vec<(&String, &mut IWantRequest)> = self.iwant_requests.iter_mut().collect();
+        requests.sort_by(|a, b| a.0.cmp(b.0));
+        let mut retries: BTreeMap<(String, String), Vec<String>> = BTreeMap::new();
+        for (message_id, request) in requests {
+            request
+                .in_flight
+                .retain(|_, &mut requested_at| current_time.saturating_sub(requested_at) < followup_time);
+
+            while request.in_flight.len() < max_in_flight {
+                // 重试同样计入每个心跳周期的请求上限，额度用完的宣告者留到下个周期
+                let Some(index) = request.advertisers.iter().position(|peer| {
+                    iasked_counts.get(peer).copied().unwrap_or(0) < max_iwant_ids
+                }) else {
+                    break;
+                };
+                let peer_id = request.advertisers.remove(index);
+                *iasked_counts.entry(peer_id.clone()).or_default() += 1;
+                request.in_flight.insert(peer_id.clone(), current_time);
+                retries
+                    .entry((peer_id, request.topic.clone()))
+                    .or_default()
+                    .push(message_id.clone());
             }
         }
+
+        for ((peer_id, topic), message_ids) in retries {
+            debug!(
+                node_id = %self.node_id,
+                peer = %peer_id,
+                topic = %topic,
+                count = message_ids.len(),
+                "重试IWANT"
+            );
+            self.send_iwant(&peer_id, &topic, message_ids)?;
+        }
+
         Ok(())
     }
 
@@ -462,151 +1071,829 @@ impl GossipSubNode {
         &mut self,
         message: GossipMessage,
         from_peer: &str,
-    ) -> Result<(), String> {
-        println!(
-            "节点 {} 从 {} 收到IWANT消息，请求 {} 个消息",
-            self.node_id,
-            from_peer,
-            message.message_ids.len()
-        );
+    ) -> Result<(), GossipSubError> {
+        trace!(requested = message.message_ids.len(), "收到IWANT");
 
-        // 发送请求的消息
-        for message_id in &message.message_ids {
+        // 发送请求的消息（最多处理max_ihave_length个ID）
+        for message_id in message.message_ids.iter().take(self.config.max_ihave_length) {
             if let Some(cached_message) = self.message_cache.get(message_id) {
+                // 限制同一消息重发给同一peer的次数
+                let count = self
+                    .retransmissions
+                    .entry(message_id.clone())
+                    .or_default()
+                    .entry(from_peer.to_string())
+                    .or_default();
+                if *count >= self.config.gossip_retransmission {
+                    self.gossip_limit_stats.ignored_retransmissions += 1;
+                    debug!(
+                        requested_id = %message_id,
+                        count = *count,
+                        "重发次数已达上限，忽略"
+                    );
+                    self.emit(GossipSubEvent::IWantUnfulfilled {
+                        peer_id: from_peer.to_string(),
+                        message_id: message_id.clone(),
+                        reason: IWantUnfulfilledReason::RetransmissionLimit,
+                    });
+                    continue;
+                }
+                *count += 1;
+
                 // 创建一个新的消息副本发送给请求者
                 let mut response_message = cached_message.clone();
                 response_message.to = Some(from_peer.to_string());
 
                 self.send_message_to_peer(from_peer, &response_message)?;
-                println!("  发送消息 {} 给 {}", message_id, from_peer);
+                trace!(requested_id = %message_id, "响应IWANT");
+                self.emit(GossipSubEvent::IWantServed {
+                    peer_id: from_peer.to_string(),
+                    message_id: message_id.clone(),
+                });
             } else {
-                println!("  消息 {} 不在缓存中，无法发送", message_id);
+                trace!(requested_id = %message_id, "请求的消息不在缓存中");
+                self.emit(GossipSubEvent::IWantUnfulfilled {
+                    peer_id: from_peer.to_string(),
+                    message_id: message_id.clone(),
+                    reason: IWantUnfulfilledReason::NotInCache,
+                });
             }
         }
         Ok(())
     }
 
+    // 消息送达，移除所有节点对该消息的IWANT承诺
+    fn fulfill_iwant_promises(&mut self, message_id: &str) {
+        for promises in self.iwant_promises.values_mut() {
+            promises.remove(message_id);
+        }
+        self.iwant_promises.retain(|_, promises| !promises.is_empty());
+    }
+
+    // 检查过期未兑现的IWANT承诺，每个违约计一次行为惩罚
+    fn penalize_broken_promises(&mut self) {
+        let current_time = self.clock.now_millis();
+        let mut broken_promises = Vec::new();
+
+        for (peer_id, promises) in self.iwant_promises.iter_mut() {
+            let before = promises.len();
+            promises.retain(|_, &mut expires_at| current_time < expires_at);
+            let broken = before - promises.len();
+            if broken > 0 {
+                broken_promises.push((peer_id.clone(), broken));
+            }
+        }
+        self.iwant_promises.retain(|_, promises| !promises.is_empty());
+
+        for (peer_id, broken) in broken_promises {
+            debug!(node_id = %self.node_id, peer = %peer_id, broken, "IWANT承诺未兑现");
+            self.add_peer_penalty(&peer_id, broken as f64);
+        }
+    }
+
     // 清理过期的IWANT请求
     fn cleanup_expired_iwant_requests(&mut self) {
-        let current_time = GossipMessage::current_timestamp();
+        let current_time = self.clock.now_millis();
         let ttl = self.config.message_cache_ttl;
 
-        self.iwant_requests
-            .retain(|_, &mut timestamp| current_time - timestamp < ttl);
+        // 超过缓存时间或已没有可请求的peer时放弃
+        self.iwant_requests.retain(|_, request| {
+            current_time.saturating_sub(request.first_requested) < ttl
+                && (!request.in_flight.is_empty() || !request.advertisers.is_empty())
+        });
     }
 
     // 清理过期的消息缓存
     pub fn cleanup_message_cache(&mut self) {
-        let current_time = GossipMessage::current_timestamp();
+        let current_time = self.clock.now_millis();
         let ttl = self.config.message_cache_ttl;
 
+        // 按加入缓存的时间过期，不依赖发送方的时间戳
+        self.message_cached_at
+            .retain(|_, cached_at| current_time.saturating_sub(*cached_at) < ttl);
+        let message_cached_at = &self.message_cached_at;
         self.message_cache
-            .retain(|_, message| current_time - message.timestamp < ttl);
+            .retain(|message_id, _| message_cached_at.contains_key(message_id));
 
-        // 同时清理seen_messages中的过期项
-        // 注意：这里简化处理，实际应该记录消息的时间戳
-        if self.seen_messages.len() > 1000 {
-            self.seen_messages.clear();
-        }
+        // 消息离开缓存后不再需要重发计数
+        let message_cache = &self.message_cache;
+        self.retransmissions
+            .retain(|message_id, _| message_cache.contains_key(message_id));
+
+        // 已见消息ID保留seen_ttl，期间重复收到的消息仍会被识别
+        let seen_ttl = self.config.seen_ttl;
+        self.seen_messages
+            .retain(|_, seen_at| current_time.saturating_sub(*seen_at) < seen_ttl);
     }
 
-    // 处理GRAFT消息
-    fn handle_graft_message(
+    // 处理订阅通知
+    fn handle_subscribe_message(
         &mut self,
         message: GossipMessage,
         from_peer: &str,
-    ) -> Result<(), String> {
-        if let Some(topic) = &message.topic {
-            println!(
-                "节点 {} 收到来自 {} 的GRAFT请求，主题: {}",
-                self.node_id, from_peer, topic
-            );
-
-            // 检查是否订阅了该主题
-            if !self.topics.contains(topic) {
-                println!("  拒绝GRAFT: 未订阅主题 {}", topic);
-                return Ok(());
-            }
-
-            // 检查是否在GRAFT洪水攻击检测中
-            if self.is_graft_flooding(topic, from_peer) {
-                println!("  拒绝GRAFT: 检测到来自 {} 的洪水攻击", from_peer);
-                return Ok(());
-            }
-
-            // 检查mesh是否已满
-            let mesh_size = self.get_mesh_size(topic);
-            if mesh_size >= self.config.mesh_high {
-                println!(
-                    "  拒绝GRAFT: mesh已满 ({}/{})",
-                    mesh_size, self.config.mesh_high
-                );
-
-                // 发送PRUNE响应
-                let prune_response = GossipMessage::new(MessageType::Prune)
-                    .with_topic(topic.clone())
-                    .with_from(self.node_id.clone())
-                    .with_to(from_peer.to_string());
-
-                self.send_message_to_peer(from_peer, &prune_response)?;
-                return Ok(());
-            }
-
-            // 接受GRAFT请求
-            self.mesh
-                .entry(topic.clone())
-                .or_insert_with(HashSet::new)
-                .insert(from_peer.to_string());
-            println!("  ✅ 接受GRAFT: {} 加入主题 {} 的mesh", from_peer, topic);
+    ) -> Result<(), GossipSubError> {
+        if let Some(topic) = message.topic {
+            debug!(topic = %topic, "对方订阅主题");
+            self.peer_topics
+                .entry(from_peer.to_string())
+                .or_default()
+                .insert(topic.clone());
+            self.emit(GossipSubEvent::PeerSubscribed {
+                peer_id: from_peer.to_string(),
+                topic,
+            });
         }
-
         Ok(())
     }
 
-    // 处理PRUNE消息
-    fn handle_prune_message(
+    // 处理取消订阅通知
+    fn handle_unsubscribe_message(
         &mut self,
         message: GossipMessage,
         from_peer: &str,
-    ) -> Result<(), String> {
+    ) -> Result<(), GossipSubError> {
         if let Some(topic) = &message.topic {
-            println!(
-                "节点 {} 收到来自 {} 的PRUNE消息，主题: {}",
-                self.node_id, from_peer, topic
-            );
-
-            // 从mesh中移除节点
-            if let Some(mesh_peers) = self.mesh.get_mut(topic) {
-                if mesh_peers.remove(from_peer) {
-                    println!("  ✅ {} 从主题 {} 的mesh中移除", from_peer, topic);
-                }
+            debug!(topic = %topic, "对方取消订阅主题");
+            if let Some(topics) = self.peer_topics.get_mut(from_peer) {
+                topics.remove(topic);
             }
 
-            // 设置GRAFT退避，防止立即重新GRAFT
-            let backoff_until = GossipMessage::current_timestamp() + self.config.graft_backoff;
-            self.graft_backoff
-                .entry(topic.clone())
-                .or_insert_with(HashMap::new)
-                .insert(from_peer.to_string(), backoff_until);
+            self.emit(GossipSubEvent::PeerUnsubscribed {
+                peer_id: from_peer.to_string(),
+                topic: topic.clone(),
+            });
+
+            // 对方不再订阅该主题，从mesh和fanout中移除
+            if let Some(mesh_peers) = self.mesh.get_mut(topic)
+                && mesh_peers.remove(from_peer)
+            {
+                self.emit(GossipSubEvent::Pruned {
+                    peer_id: from_peer.to_string(),
+                    topic: topic.clone(),
+                    direction: ConnectionDirection::Inbound,
+                    reason: PruneReason::PeerUnsubscribed,
+                });
+            }
+            if let Some(fanout_peers) = self.fanout.get_mut(topic) {
+                fanout_peers.remove(from_peer);
+            }
         }
-
         Ok(())
     }
 
-    // 检测GRAFT洪水攻击
-    fn is_graft_flooding(&self, _topic: &str, _from_peer: &str) -> bool {
-        // 简化实现：这里应该跟踪每个peer的GRAFT频率
-        // 实际实现中需要维护一个时间窗口内的GRAFT计数
-        false
+    // 向节点通知订阅状态的变化
+    fn announce_subscription(
+        &mut self,
+        peer_id: &str,
+        topic: &str,
+        message_type: MessageType,
+    ) -> Result<(), GossipSubError> {
+        let message = self.new_message(message_type)
+            .with_topic(topic.to_string())
+            .with_from(self.node_id.clone())
+            .with_to(peer_id.to_string());
+        self.send_message_to_peer(peer_id, &message)
     }
 
-    // 清理过期的退避状态
-    pub fn cleanup_backoffs(&mut self) {
-        let current_time = GossipMessage::current_timestamp();
-
-        // 清理GRAFT退避
-        for topic_backoffs in self.graft_backoff.values_mut() {
-            topic_backoffs.retain(|_, &mut backoff_until| current_time < backoff_until);
+    // 向peer请求主题的历史消息，返回请求消息ID，响应通过HistoryReceived事件通知
+    pub fn request_history(
+        &mut self,
+        peer_id: &str,
+        topic: &str,
+        query: HistoryQuery,
+    ) -> Result<String, GossipSubError> {
+        if !self.topics.contains(topic) {
+            return Err(GossipSubError::NotSubscribed(topic.to_string()));
+        }
+        if !self.peers.contains_key(peer_id) {
+            return Err(GossipSubError::InsufficientPeers(topic.to_string()));
+        }
+        self.send_history_request(peer_id, topic, query, false)
+    }
+
+    fn send_history_request(
+        &mut self,
+        peer_id: &str,
+        topic: &str,
+        query: HistoryQuery,
+        sync: bool,
+    ) -> Result<String, GossipSubError> {
+        let request = self.new_message(MessageType::HistoryRequest)
+            .with_topic(topic.to_string())
+            .with_from(self.node_id.clone())
+            .with_to(peer_id.to_string())
+            .with_history(query.clone());
+        let request_id = request.message_id.clone();
+        self.history_requests.insert(
+            request_id.clone(),
+            HistoryRequest {
+                peer_id: peer_id.to_string(),
+                topic: topic.to_string(),
+                query,
+                sent_at: self.clock.now_millis(),
+                sync,
+            },
+        );
+        debug!(node_id = %self.node_id, peer = peer_id, topic, sync, "发送历史请求");
+        self.send_message_to_peer(peer_id, &request)?;
+        Ok(request_id)
+    }
+
+    // 订阅后开始同步历史：本地有窗口内的历史时从最新一条之后补齐，否则取窗口内最新的消息
+    fn start_history_sync(&mut self, topic: &str) -> Result<(), GossipSubError> {
+        if self.config.history_sync_messages == 0 {
+            return Ok(());
+        }
+        let limit = self
+            .config
+            .history_sync_messages
+            .min(self.config.history_page_size);
+        let window_start = self
+            .clock
+            .unix_millis()
+            .saturating_sub(self.config.history_sync_window);
+        let newest = self
+            .store
+            .as_ref()
+            .and_then(|store| store.latest(topic, u64::MAX, 1).pop())
+            .filter(|message| message.timestamp >= window_start);
+        let query = match newest {
+            Some(message) => HistoryQuery {
+                from: Some(message.timestamp),
+                after: Some(message.message_id),
+                limit,
+                ..HistoryQuery::default()
+            },
+            None => HistoryQuery {
+                from: Some(window_start),
+                latest: true,
+                limit,
+                ..HistoryQuery::default()
+            },
+        };
+
+        self.history_syncs.insert(
+            topic.to_string(),
+            HistorySync {
+                query,
+                received: 0,
+                tried: HashSet::new(),
+                in_flight: None,
+            },
+        );
+        self.advance_history_sync(topic)
+    }
+
+    // 没有进行中的请求时，向一个未请求过的mesh节点发送同步请求
+    fn advance_history_sync(&mut self, topic: &str) -> Result<(), GossipSubError> {
+        let Some(sync) = self.history_syncs.get(topic) else {
+            return Ok(());
+        };
+        if sync.in_flight.is_some() {
+            return Ok(());
+        }
+        let candidates: Vec<String> = self
+            .mesh
+            .get(topic)
+            .into_iter()
+            .flatten()
+            .filter(|&peer_id| {
+                !sync.tried.contains(peer_id)
+                    && self
+                        .peer_topics
+                        .get(peer_id)
+                        .is_some_and(|topics| topics.contains(topic))
+            })
+            .cloned()
+            .collect();
+        let (query, tried_any) = (sync.query.clone(), !sync.tried.is_empty());
+
+        let Some(peer_id) = self.random_peers(candidates, 1).pop() else {
+            // 已请求过所有mesh节点时放弃，mesh为空时等下次心跳再试
+            if tried_any {
+                debug!(node_id = %self.node_id, topic, "没有可请求的mesh节点，停止历史同步");
+                self.history_syncs.remove(topic);
+            }
+            return Ok(());
+        };
+        let request_id = self.send_history_request(&peer_id, topic, query, true)?;
+        if let Some(sync) = self.history_syncs.get_mut(topic) {
+            sync.tried.insert(peer_id);
+            sync.in_flight = Some(request_id);
+        }
+        Ok(())
+    }
+
+    // 收到同步请求的响应后，继续向同一节点请求下一页，或结束同步
+    fn continue_history_sync(
+        &mut self,
+        topic: &str,
+        request_id: &str,
+        peer_id: &str,
+        received: usize,
+        next: Option<HistoryQuery>,
+    ) -> Result<(), GossipSubError> {
+        let Some(sync) = self.history_syncs.get_mut(topic) else {
+            return Ok(());
+        };
+        if sync.in_flight.as_deref() != Some(request_id) {
+            return Ok(());
+        }
+        sync.in_flight = None;
+        sync.received += received;
+        let remaining = self
+            .config
+            .history_sync_messages
+            .saturating_sub(sync.received);
+
+        match next {
+            Some(mut next) if remaining > 0 => {
+                next.limit = remaining.min(self.config.history_page_size);
+                sync.query = next.clone();
+                let request_id = self.send_history_request(peer_id, topic, next, true)?;
+                if let Some(sync) = self.history_syncs.get_mut(topic) {
+                    sync.in_flight = Some(request_id);
+                }
+            }
+            // 对方没有任何历史（例如没有配置存储）时换其他mesh节点
+            None if sync.received == 0 => self.advance_history_sync(topic)?,
+            _ => {
+                debug!(node_id = %self.node_id, topic, received = sync.received, "历史同步完成");
+                self.history_syncs.remove(topic);
+            }
+        }
+        Ok(())
+    }
+
+    // 处理超时的历史请求，并推进各主题的历史同步
+    fn maintain_history_syncs(&mut self) -> Result<(), GossipSubError> {
+        let current_time = self.clock.now_millis();
+        let timeout = self.config.history_request_timeout;
+        let expired: Vec<String> = self
+            .history_requests
+            .iter()
+            .filter(|(_, request)| current_time.saturating_sub(request.sent_at) >= timeout)
+            .map(|(request_id, _)| request_id.clone())
+            .collect();
+        for request_id in expired {
+            if let Some(request) = self.history_requests.remove(&request_id) {
+                debug!(node_id = %self.node_id, peer = %request.peer_id, topic = %request.topic, "历史请求超时");
+                self.clear_history_in_flight(&request.topic, &request_id);
+            }
+        }
+
+        let mut topics: Vec<String> = self.history_syncs.keys().cloned().collect();
+        topics.sort();
+        for topic in topics {
+            self.advance_history_sync(&topic)?;
+        }
+        Ok(())
+    }
+
+    // 请求失败后允许同步换其他节点重试
+    fn clear_history_in_flight(&mut self, topic: &str, request_id: &str) {
+        if let Some(sync) = self.history_syncs.get_mut(topic)
+            && sync.in_flight.as_deref() == Some(request_id)
+        {
+            sync.in_flight = None;
+        }
+    }
+
+    // 处理历史请求：从消息存储中取出一页消息返回给对方
+    fn handle_history_request(
+        &mut self,
+        message: GossipMessage,
+        from_peer: &str,
+    ) -> Result<(), GossipSubError> {
+        let (Some(topic), Some(query)) = (message.topic.clone(), message.history.clone()) else {
+            warn!("丢弃缺少查询的历史请求");
+            self.add_peer_penalty(from_peer, 1.0);
+            self.metrics.invalid += 1;
+            let error = GossipSubError::ValidationFailed(format!(
+                "历史请求 {} 缺少查询",
+                message.message_id
+            ));
+            self.emit_invalid(&message, from_peer, &error);
+            return Err(error);
+        };
+
+        // 起止时间颠倒的查询不可能有结果，按无效请求处理
+        if let (Some(from), Some(to)) = (query.from, query.to)
+            && from > to
+        {
+            warn!(from, to, "丢弃时间范围颠倒的历史请求");
+            self.add_peer_penalty(from_peer, 1.0);
+            self.metrics.invalid += 1;
+            let error = GossipSubError::ValidationFailed(format!(
+                "历史请求 {} 的时间范围颠倒: {} > {}",
+                message.message_id, from, to
+            ));
+            self.emit_invalid(&message, from_peer, &error);
+            return Err(error);
+        }
+
+        // 每个心跳周期内只接受有限数量的历史请求
+        let count = self
+            .history_request_counts
+            .entry(from_peer.to_string())
+            .or_default();
+        *count += 1;
+        if *count > self.config.max_history_requests {
+            self.gossip_limit_stats.ignored_history_requests += 1;
+            debug!(count = *count, "本周期历史请求已达上限，忽略");
+            return Ok(());
+        }
+
+        let mut response = self.new_message(MessageType::HistoryResponse)
+            .with_topic(topic.clone())
+            .with_from(self.node_id.clone())
+            .with_to(from_peer.to_string())
+            .with_request_id(message.message_id);
+
+        // 只提供本节点订阅的主题，没有存储或未订阅时返回空页，对方会换其他节点
+        let (page, next) = if self.topics.contains(&topic) {
+            self.history_page(&topic, &query, response.encoded_len())
+        } else {
+            (Vec::new(), None)
+        };
+        let count = page.len();
+        debug!(topic = %topic, count, more = next.is_some(), "返回历史消息");
+
+        response = if query.ids_only {
+            response.with_message_ids(page.into_iter().map(|m| m.message_id).collect())
+        } else {
+            response.with_messages(page)
+        };
+        if let Some(next) = next {
+            response = response.with_history(next);
+        }
+        self.emit(GossipSubEvent::HistoryServed {
+            peer_id: from_peer.to_string(),
+            topic,
+            count,
+        });
+        self.send_message_to_peer(from_peer, &response)
+    }
+
+    // 按查询从消息存储中取出一页消息，页面加上响应本身不超过max_transmit_size
+    // 返回的下一页查询为None表示没有更多消息
+    fn history_page(
+        &self,
+        topic: &str,
+        query: &HistoryQuery,
+        envelope_len: usize,
+    ) -> (Vec<GossipMessage>, Option<HistoryQuery>) {
+        let limit = query.limit.min(self.config.history_page_size);
+        let Some(store) = self.store.as_deref().filter(|_| limit > 0) else {
+            return (Vec::new(), None);
+        };
+        let from = query.from.unwrap_or(0);
+        let to = query.to.unwrap_or(u64::MAX);
+
+        // 多取一条用于判断是否还有下一页；latest取的是范围末尾，之后没有更多消息
+        let (candidates, tail) = match &query.after {
+            Some(after) if store.get(after).is_some() => (store.since(topic, after, limit + 1), false),
+            _ if query.latest => (store.latest(topic, to, limit), true),
+            _ => (store.range(topic, from, to, limit + 1), false),
+        };
+        let candidates: Vec<GossipMessage> = candidates
+            .into_iter()
+            .filter(|message| (from..=to).contains(&message.timestamp))
+            .collect();
+
+        let mut page = Vec::new();
+        let mut cursor = None;
+        let mut truncated = false;
+        let mut used = envelope_len;
+        for message in candidates.iter().take(limit) {
+            let size = if query.ids_only {
+                message.message_id.len()
+            } else {
+                message.encoded_len()
+            };
+            // 预留下一页查询的大小
+            let next_size = message.message_id.len() + 3 * std::mem::size_of::<u64>();
+            if used + size + next_size > self.config.max_transmit_size {
+                truncated = true;
+                if page.is_empty() {
+                    // 单条消息已无法放入响应，跳过它以免分页停滞
+                    warn!(message_id = %message.message_id, "历史消息过大，跳过");
+                    cursor = Some(message);
+                }
+                break;
+            }
+            used += size;
+            page.push(message.clone());
+            cursor = Some(message);
+        }
+
+        let more = truncated || (!tail && candidates.len() > limit);
+        let next = cursor.filter(|_| more).map(|message| HistoryQuery {
+            from: Some(message.timestamp),
+            to: query.to,
+            after: Some(message.message_id.clone()),
+            latest: false,
+            limit: query.limit,
+            ids_only: query.ids_only,
+        });
+        (page, next)
+    }
+
+    // 处理历史响应：接受其中未见过的消息，自动同步时继续请求下一页
+    fn handle_history_response(
+        &mut self,
+        message: GossipMessage,
+        from_peer: &str,
+    ) -> Result<(), GossipSubError> {
+        // 只接受本节点向该peer发出的请求的响应
+        let request_id = message.request_id.clone().unwrap_or_default();
+        let Some(request) = self.history_requests.get(&request_id).filter(|request| {
+            request.peer_id == from_peer && message.topic.as_ref() == Some(&request.topic)
+        }) else {
+            debug!("忽略未请求的历史响应");
+            return Ok(());
+        };
+        let (topic, query, sync) = (request.topic.clone(), request.query.clone(), request.sync);
+        self.history_requests.remove(&request_id);
+
+        // 对方返回的消息不能超过请求的数量
+        let mut messages = message.messages;
+        let mut message_ids = message.message_ids;
+        if messages.len() + message_ids.len() > query.limit {
+            debug!(limit = query.limit, "历史响应超过请求数量，截断");
+            messages.truncate(query.limit);
+            message_ids.truncate(query.limit - messages.len());
+        }
+
+        for history_message in messages {
+            // 历史消息没有经过正常的转发路径，逐条检查后才接受
+            if let Err(reason) = self.check_history_message(&history_message, &topic, &query) {
+                warn!(message_id = %history_message.message_id, reason, "丢弃不合法的历史消息");
+                self.add_peer_penalty(from_peer, 1.0);
+                self.metrics.invalid += 1;
+                continue;
+            }
+            message_ids.push(history_message.message_id.clone());
+
+            let message_id = &history_message.message_id;
+            if self.seen_messages.contains_key(message_id)
+                || self
+                    .store
+                    .as_ref()
+                    .is_some_and(|store| store.get(message_id).is_some())
+            {
+                continue;
+            }
+            self.seen_messages.insert(message_id.clone(), self.clock.now_millis());
+            self.fulfill_iwant_promises(message_id);
+            self.iwant_requests.remove(message_id);
+
+            // 历史消息只写入消息存储，不进入消息缓存，因此不会被转发、宣告或通过IWANT提供
+            if let Some(store) = self.store.as_mut()
+                && let Err(e) = store.append(&history_message)
+            {
+                warn!(message_id = %message_id, error = %e, "写入消息存储失败");
+            }
+            self.emit(GossipSubEvent::MessageReceived {
+                message: history_message,
+                propagation_source: from_peer.to_string(),
+            });
+        }
+        debug!(topic = %topic, count = message_ids.len(), more = message.history.is_some(), "收到历史消息");
+
+        self.emit(GossipSubEvent::HistoryReceived {
+            peer_id: from_peer.to_string(),
+            topic: topic.clone(),
+            message_ids: message_ids.clone(),
+            next: message.history.clone(),
+        });
+        if sync {
+            self.continue_history_sync(
+                &topic,
+                &request_id,
+                from_peer,
+                message_ids.len(),
+                message.history,
+            )?;
+        }
+        Ok(())
+    }
+
+    // 检查历史响应中的单条消息：属于请求的主题和时间范围，大小不超过上限
+    fn check_history_message(
+        &self,
+        message: &GossipMessage,
+        topic: &str,
+        query: &HistoryQuery,
+    ) -> Result<(), &'static str> {
+        if message.message_type != MessageType::Publish || message.topic.as_deref() != Some(topic) {
+            return Err("不属于该主题");
+        }
+        if message.message_id.is_empty() {
+            return Err("缺少消息ID");
+        }
+        if message.encoded_len() > self.config.max_transmit_size {
+            return Err("消息过大");
+        }
+        let from = query.from.unwrap_or(0);
+        let to = query.to.unwrap_or(u64::MAX);
+        if !(from..=to).contains(&message.timestamp) {
+            return Err("时间戳不在请求范围内");
+        }
+        if message.timestamp > self.clock.unix_millis().saturating_add(HISTORY_CLOCK_SKEW) {
+            return Err("时间戳晚于当前时间");
+        }
+        Ok(())
+    }
+
+    // 处理GRAFT消息
+    fn handle_graft_message(
+        &mut self,
+        message: GossipMessage,
+        from_peer: &str,
+    ) -> Result<(), GossipSubError> {
+        if let Some(topic) = &message.topic {
+            // 检查是否订阅了该主题
+            if !self.topics.contains(topic) {
+                debug!(topic = %topic, "拒绝GRAFT: 未订阅主题");
+                self.emit_graft_rejected(topic, from_peer, GraftRejectReason::NotSubscribed);
+                return Ok(());
+            }
+
+            // 直连节点不应加入mesh
+            if self.is_direct_peer(from_peer) {
+                warn!(topic = %topic, "拒绝GRAFT: 对方是直连节点");
+                self.add_peer_penalty(from_peer, 1.0);
+
+                let prune_response = self.new_message(MessageType::Prune)
+                    .with_topic(topic.clone())
+                    .with_from(self.node_id.clone())
+                    .with_to(from_peer.to_string())
+                    .with_backoff(self.config.prune_backoff);
+
+                self.send_message_to_peer(from_peer, &prune_response)?;
+                self.emit_graft_rejected(topic, from_peer, GraftRejectReason::DirectPeer);
+                return Ok(());
+            }
+
+            // 我们PRUNE对方后的退避期内不接受GRAFT，回复PRUNE以免对方单方面保留mesh关系
+            if self.is_peer_in_backoff(topic, from_peer, false) {
+                debug!(topic = %topic, "拒绝GRAFT: 对方仍在退避期内");
+                self.add_peer_penalty(from_peer, 1.0);
+
+                let prune_response = self.new_message(MessageType::Prune)
+                    .with_topic(topic.clone())
+                    .with_from(self.node_id.clone())
+                    .with_to(from_peer.to_string())
+                    .with_backoff(self.config.prune_backoff);
+
+                self.send_message_to_peer(from_peer, &prune_response)?;
+                self.emit_graft_rejected(topic, from_peer, GraftRejectReason::Backoff);
+                return Ok(());
+            }
+
+            // 检查是否在GRAFT洪水攻击检测中
+            if self.is_graft_flooding(topic, from_peer) {
+                warn!(topic = %topic, "拒绝GRAFT: 检测到洪水攻击");
+                self.emit_graft_rejected(topic, from_peer, GraftRejectReason::Flood);
+                return Ok(());
+            }
+
+            // 检查mesh是否已满（出站节点不受此限制，以保证出站配额）
+            let mesh_size = self.get_mesh_size(topic);
+            if mesh_size >= self.config.mesh_high && !self.is_outbound(from_peer) {
+                debug!(
+                    topic = %topic,
+                    mesh_size,
+                    mesh_high = self.config.mesh_high,
+                    "拒绝GRAFT: mesh已满"
+                );
+
+                // 发送PRUNE响应，附带其他mesh节点供对方连接
+                let prune_response = self.new_message(MessageType::Prune)
+                    .with_topic(topic.clone())
+                    .with_from(self.node_id.clone())
+                    .with_to(from_peer.to_string())
+                    .with_peers(self.px_peers(topic, from_peer))
+                    .with_backoff(self.config.prune_backoff);
+
+                self.send_message_to_peer(from_peer, &prune_response)?;
+                self.emit_graft_rejected(topic, from_peer, GraftRejectReason::MeshFull);
+                return Ok(());
+            }
+
+            // 接受GRAFT请求
+            let inserted = self
+                .mesh
+                .entry(topic.clone())
+                .or_default()
+                .insert(from_peer.to_string());
+            if inserted {
+                debug!(topic = %topic, "接受GRAFT，对方加入mesh");
+                self.emit(GossipSubEvent::Grafted {
+                    peer_id: from_peer.to_string(),
+                    topic: topic.clone(),
+                    direction: ConnectionDirection::Inbound,
+                    reason: GraftReason::RemoteRequest,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    // 处理PRUNE消息
+    fn handle_prune_message(
+        &mut self,
+        message: GossipMessage,
+        from_peer: &str,
+    ) -> Result<(), GossipSubError> {
+        if let Some(topic) = &message.topic {
+            // 从mesh中移除节点
+            if let Some(mesh_peers) = self.mesh.get_mut(topic)
+                && mesh_peers.remove(from_peer)
+            {
+                debug!(topic = %topic, "收到PRUNE，对方从mesh中移除");
+                self.emit(GossipSubEvent::Pruned {
+                    peer_id: from_peer.to_string(),
+                    topic: topic.clone(),
+                    direction: ConnectionDirection::Inbound,
+                    reason: PruneReason::RemoteRequest,
+                });
+            }
+
+            // 设置GRAFT退避，防止立即重新GRAFT；取本地配置和对方要求的较大值
+            // 对方的值不可信，先限制在max_prune_backoff以内
+            let backoff = message
+                .backoff
+                .map_or(self.config.graft_backoff, |remote| {
+                    remote
+                        .min(self.config.max_prune_backoff)
+                        .max(self.config.graft_backoff)
+                });
+            let backoff_until = self.clock.now_millis().saturating_add(backoff);
+            self.graft_backoff
+                .entry(topic.clone())
+                .or_default()
+                .insert(from_peer.to_string(), backoff_until);
+
+            // mesh不足时，利用对方提供的PX节点建立新连接
+            if !message.peers.is_empty() {
+                self.connect_px_peers(topic, from_peer, &message.peers);
+            }
+        }
+
+        Ok(())
+    }
+
+    // 连接PRUNE中附带的PX节点
+    fn connect_px_peers(&mut self, topic: &str, from_peer: &str, px_peers: &[PeerInfo]) {
+        if self.get_mesh_size(topic) >= self.config.mesh_low {
+            return;
+        }
+
+        let score = self.peer_score(from_peer);
+        if score < self.config.score_thresholds.accept_px_threshold {
+            debug!(
+                score,
+                threshold = self.config.score_thresholds.accept_px_threshold,
+                "对方评分过低，忽略PX"
+            );
+            return;
+        }
+
+        let new_peers: Vec<PeerInfo> = px_peers
+            .iter()
+            .filter(|peer| peer.peer_id != self.node_id && !self.peers.contains_key(&peer.peer_id))
+            .take(self.config.prune_peers)
+            .cloned()
+            .collect();
+
+        for peer in new_peers {
+            // peer记录中携带连接信息（未签名，不能证明对方身份），缺失时只能通过peerId寻址
+            let connection_info = peer
+                .signed_peer_record
+                .map(|record| String::from_utf8_lossy(&record).into_owned())
+                .unwrap_or_else(|| format!("px_from_{}", from_peer));
+            debug!(px_peer = %peer.peer_id, "通过PX发现节点");
+            self.add_peer(peer.peer_id, connection_info);
+        }
+    }
+
+    // 检测GRAFT洪水攻击
+    fn is_graft_flooding(&self, _topic: &str, _from_peer: &str) -> bool {
+        // 简化实现：这里应该跟踪每个peer的GRAFT频率
+        // 实际实现中需要维护一个时间窗口内的GRAFT计数
+        false
+    }
+
+    // 清理过期的退避状态
+    pub fn cleanup_backoffs(&mut self) {
+        let current_time = self.clock.now_millis();
+
+        // 清理GRAFT退避
+        for topic_backoffs in self.graft_backoff.values_mut() {
+            topic_backoffs.retain(|_, &mut backoff_until| current_time < backoff_until);
         }
 
         // 清理PRUNE退避
@@ -621,60 +1908,1575 @@ impl GossipSubNode {
             .retain(|_, backoffs| !backoffs.is_empty());
     }
 
-    // 添加对等节点连接
-    pub fn add_peer(&mut self, peer_id: String, connection_info: String) {
-        self.peers.insert(peer_id.clone(), connection_info);
-        println!("节点 {} 连接到对等节点 {}", self.node_id, peer_id);
+    // 获取节点评分
+    pub fn peer_score(&self, peer_id: &str) -> f64 {
+        self.peer_scores
+            .get(peer_id)
+            .map_or(0.0, |score| score.score(&self.config.score_params))
     }
 
-    // 订阅主题
-    pub fn subscribe(&mut self, topic: String) {
-        if !self.topics.contains(&topic) {
-            self.topics.insert(topic.clone());
-            println!("节点 {} 订阅主题: {}", self.node_id, topic);
+    // 对节点施加行为惩罚
+    fn add_peer_penalty(&mut self, peer_id: &str, count: f64) {
+        let old_score = self.peer_score(peer_id);
+        self.peer_scores
+            .entry(peer_id.to_string())
+            .or_default()
+            .add_penalty(count);
+        self.emit_score_change(peer_id, old_score);
+    }
 
-            // 初始化该主题的mesh网络
-            self.initialize_mesh(&topic);
+    // 衰减所有节点的行为惩罚
+    fn decay_peer_scores(&mut self) {
+        let params = &self.config.score_params;
+        let mut changed = Vec::new();
+        for (peer_id, score) in self.peer_scores.iter_mut() {
+            let old_score = score.score(params);
+            score.decay(params);
+            if score.score(params) != old_score {
+                changed.push((peer_id.clone(), old_score));
+            }
+        }
+
+        for (peer_id, old_score) in changed {
+            self.emit_score_change(&peer_id, old_score);
         }
     }
 
-    // 初始化主题的mesh网络
-    fn initialize_mesh(&mut self, topic: &str) {
-        if !self.mesh.contains_key(topic) {
-            self.mesh.insert(topic.to_string(), HashSet::new());
+    fn emit_score_change(&mut self, peer_id: &str, old_score: f64) {
+        let new_score = self.peer_score(peer_id);
+        if new_score != old_score {
+            self.emit(GossipSubEvent::PeerScoreChanged {
+                peer_id: peer_id.to_string(),
+                old_score,
+                new_score,
+            });
         }
+    }
 
-        // 获取所有对等节点（这里简化处理，实际应该检查对等节点是否订阅了相同的主题)
-        let available_peers: Vec<String> = self.peers.keys().cloned().collect();
+    // 订阅协议事件
+    pub fn event_stream(&mut self) -> UnboundedReceiver<GossipSubEvent> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.event_senders.push(sender);
+        receiver
+    }
 
-        let mesh_peers = self.mesh.get_mut(topic).unwrap();
-        let needed = std::cmp::min(self.config.mesh_size, available_peers.len());
+    // 向所有订阅者发送事件，丢弃已关闭的订阅
+    fn emit(&mut self, event: GossipSubEvent) {
+        if self.event_senders.is_empty() {
+            return;
+        }
+        self.event_senders
+            .retain(|sender| sender.send(event.clone()).is_ok());
+    }
 
-        // 随机选择节点加入mesh(这里简化为顺序选择)
-        for (i, peer_id) in available_peers.iter().enumerate() {
-            if i >= needed {
-                break;
+    fn emit_graft_rejected(&mut self, topic: &str, peer_id: &str, reason: GraftRejectReason) {
+        self.emit(GossipSubEvent::GraftRejected {
+            peer_id: peer_id.to_string(),
+            topic: topic.to_string(),
+            reason,
+        });
+    }
+
+    fn emit_invalid(&mut self, message: &GossipMessage, peer_id: &str, error: &GossipSubError) {
+        self.emit(GossipSubEvent::MessageInvalid {
+            message_id: message.message_id.clone(),
+            peer_id: peer_id.to_string(),
+            reason: error.to_string(),
+        });
+    }
+
+    // 添加对等节点连接
+    pub fn add_peer(&mut self, peer_id: String, connection_info: String) {
+        self.insert_peer(peer_id, connection_info, ConnectionDirection::Outbound);
+    }
+
+    // 移除断开的对等节点
+    pub fn remove_peer(&mut self, peer_id: &str) {
+        if self.peers.remove(peer_id).is_none() {
+            return;
+        }
+        info!(node_id = %self.node_id, peer = peer_id, "与对等节点断开连接");
+
+        self.peer_topics.remove(peer_id);
+        let mut pruned_topics = Vec::new();
+        for (topic, mesh_peers) in self.mesh.iter_mut() {
+            if mesh_peers.remove(peer_id) {
+                pruned_topics.push(topic.clone());
             }
-            mesh_peers.insert(peer_id.clone());
+        }
+        pruned_topics.sort();
+        for topic in pruned_topics {
+            self.emit(GossipSubEvent::Pruned {
+                peer_id: peer_id.to_string(),
+                topic,
+                direction: ConnectionDirection::Inbound,
+                reason: PruneReason::PeerDisconnected,
+            });
+        }
+        for fanout_peers in self.fanout.values_mut() {
+            fanout_peers.remove(peer_id);
         }
 
-        println!(
-            "节点 {} 在主题 {} 的mesh中有 {} 个节点",
-            self.node_id,
-            topic,
-            mesh_peers.len()
-        );
+        // 清理该peer的gossip状态，进行中的IWANT让出名额，由其他宣告者重试
+        self.iwant_promises.remove(peer_id);
+        self.ihave_counts.remove(peer_id);
+        self.iasked_counts.remove(peer_id);
+        for request in self.iwant_requests.values_mut() {
+            request.in_flight.remove(peer_id);
+            request.advertisers.retain(|advertiser| advertiser != peer_id);
+        }
+
+        // 发给该peer的历史请求不会再有响应
+        let requests: Vec<(String, String)> = self
+            .history_requests
+            .iter()
+            .filter(|(_, request)| request.peer_id == peer_id)
+            .map(|(request_id, request)| (request_id.clone(), request.topic.clone()))
+            .collect();
+        for (request_id, topic) in requests {
+            self.history_requests.remove(&request_id);
+            self.clear_history_in_flight(&topic, &request_id);
+        }
+        self.history_request_counts.remove(peer_id);
     }
 
-    // 获取mesh中的节点数量
-    pub fn get_mesh_size(&self, topic: &str) -> usize {
-        self.mesh.get(topic).map_or(0, |peers| peers.len())
+    // 检查是否为直连节点
+    pub fn is_direct_peer(&self, peer_id: &str) -> bool {
+        self.config.direct_peers.contains_key(peer_id)
     }
 
-    // 检查是否在某个主题的mesh中
-    pub fn is_in_mesh(&self, topic: &str, peer_id: &str) -> bool {
+    // 重新连接断开的直连节点
+    fn reconnect_direct_peers(&mut self) {
+        let disconnected: Vec<(String, String)> = self
+            .config
+            .direct_peers
+            .iter()
+            .filter(|&(peer_id, _)| !self.peers.contains_key(peer_id))
+            .map(|(peer_id, connection_info)| (peer_id.clone(), connection_info.clone()))
+            .collect();
+
+        for (peer_id, connection_info) in disconnected {
+            info!(node_id = %self.node_id, peer = %peer_id, "重新连接直连节点");
+            self.add_peer(peer_id, connection_info);
+        }
+    }
+
+    // 添加对方主动发起的入站连接
+    pub fn add_inbound_peer(&mut self, peer_id: String, connection_info: String) {
+        self.insert_peer(peer_id, connection_info, ConnectionDirection::Inbound);
+    }
+
+    fn insert_peer(
+        &mut self,
+        peer_id: String,
+        connection_info: String,
+        direction: ConnectionDirection,
+    ) {
+        self.peers.insert(
+            peer_id.clone(),
+            PeerConnection {
+                connection_info,
+                direction,
+            },
+        );
+        info!(node_id = %self.node_id, peer = %peer_id, ?direction, "连接到对等节点");
+
+        // 新连接建立后告知对方我们订阅的主题
+        for topic in self.sorted_topics() {
+            if let Err(e) = self.announce_subscription(&peer_id, &topic, MessageType::Subscribe) {
+                warn!(node_id = %self.node_id, peer = %peer_id, error = %e, "发送订阅通知失败");
+            }
+        }
+    }
+
+    // 检查是否为出站连接
+    pub fn is_outbound(&self, peer_id: &str) -> bool {
+        self.peers
+            .get(peer_id)
+            .is_some_and(|peer| peer.direction == ConnectionDirection::Outbound)
+    }
+
+    // 获取mesh中出站连接的数量
+    pub fn outbound_mesh_count(&self, topic: &str) -> usize {
+        self.mesh.get(topic).map_or(0, |peers| {
+            peers.iter().filter(|peer_id| self.is_outbound(peer_id)).count()
+        })
+    }
+
+    // 订阅主题
+    pub fn subscribe(&mut self, topic: String) {
+        if !self.topics.contains(&topic) {
+            self.topics.insert(topic.clone());
+            info!(node_id = %self.node_id, topic = %topic, "订阅主题");
+
+            // 通知所有对等节点
+            for peer_id in self.sorted_peers() {
+                if let Err(e) = self.announce_subscription(&peer_id, &topic, MessageType::Subscribe)
+                {
+                    warn!(node_id = %self.node_id, peer = %peer_id, error = %e, "发送订阅通知失败");
+                }
+            }
+
+            // 初始化该主题的mesh网络
+            self.initialize_mesh(&topic);
+
+            // 向mesh节点同步订阅前的历史消息
+            if let Err(e) = self.start_history_sync(&topic) {
+                warn!(node_id = %self.node_id, topic = %topic, error = %e, "发送历史请求失败");
+            }
+        }
+    }
+
+    // 取消订阅主题，向mesh中的节点发送PRUNE
+    pub fn unsubscribe(&mut self, topic: &str) -> Result<(), GossipSubError> {
+        if !self.topics.remove(topic) {
+            return Ok(());
+        }
+        info!(node_id = %self.node_id, topic, "取消订阅主题");
+
+        // 通知所有对等节点
+        for peer_id in self.sorted_peers() {
+            self.announce_subscription(&peer_id, topic, MessageType::Unsubscribe)?;
+        }
+
+        let mut mesh_peers: Vec<String> = self
+            .mesh
+            .get(topic)
+            .map(|peers| peers.iter().cloned().collect())
+            .unwrap_or_default();
+        mesh_peers.sort();
+        for peer_id in mesh_peers {
+            self.prune_peer_from_mesh(
+                topic,
+                &peer_id,
+                self.config.unsubscribe_backoff,
+                PruneReason::Unsubscribed,
+            )?;
+        }
+        self.mesh.remove(topic);
+        self.history_syncs.remove(topic);
+        self.history_requests.retain(|_, request| request.topic != topic);
+
+        Ok(())
+    }
+
+    // 初始化主题的mesh网络，与维护mesh时一样通过GRAFT加入节点
+    fn initialize_mesh(&mut self, topic: &str) {
+        self.mesh.entry(topic.to_string()).or_default();
+
+        // 候选节点依次为：该主题的fanout节点、已知订阅了该主题的节点、订阅通知尚未到达的其他节点
+        let fanout_peers = self.fanout.remove(topic).unwrap_or_default();
+        let mut tiers: [Vec<String>; 3] = Default::default();
+        for peer_id in self.peers.keys() {
+            if self.is_direct_peer(peer_id) || self.is_peer_in_backoff(topic, peer_id, true) {
+                continue;
+            }
+            let tier = if fanout_peers.contains(peer_id) {
+                0
+            } else if self
+                .peer_topics
+                .get(peer_id)
+                .is_some_and(|topics| topics.contains(topic))
+            {
+                1
+            } else {
+                2
+            };
+            tiers[tier].push(peer_id.clone());
+        }
+
+        let mut selected = Vec::new();
+        for candidates in tiers {
+            let needed = self.config.mesh_size.saturating_sub(selected.len());
+            selected.extend(self.random_peers(candidates, needed));
+        }
+        for peer_id in selected {
+            if let Err(e) = self.graft_peer(topic, &peer_id, GraftReason::MeshInit) {
+                warn!(node_id = %self.node_id, peer = %peer_id, error = %e, "发送GRAFT失败");
+            }
+        }
+
+        debug!(
+            node_id = %self.node_id,
+            topic,
+            mesh_size = self.get_mesh_size(topic),
+            "初始化mesh"
+        );
+    }
+
+    // 按名称排序的订阅主题和对等节点，消除HashMap迭代顺序对消息序号和rng的影响
+    fn sorted_topics(&self) -> Vec<String> {
+        let mut topics: Vec<String> = self.topics.iter().cloned().collect();
+        topics.sort();
+        topics
+    }
+
+    fn sorted_peers(&self) -> Vec<String> {
+        let mut peers: Vec<String> = self.peers.keys().cloned().collect();
+        peers.sort();
+        peers
+    }
+
+    // 从候选节点中随机选择最多count个
+    // 先排序以消除HashMap迭代顺序的影响，保证相同种子下结果可复现
+    fn random_peers(&mut self, mut candidates: Vec<String>, count: usize) -> Vec<String> {
+        candidates.sort();
+        candidates.shuffle(&mut self.rng);
+        candidates.truncate(count);
+        candidates
+    }
+
+    // 获取mesh中的节点数量
+    pub fn get_mesh_size(&self, topic: &str) -> usize {
+        self.mesh.get(topic).map_or(0, |peers| peers.len())
+    }
+
+    // 检查是否在某个主题的mesh中
+    pub fn is_in_mesh(&self, topic: &str, peer_id: &str) -> bool {
         self.mesh
             .get(topic)
-            .map_or(false, |peers| peers.contains(peer_id))
+            .is_some_and(|peers| peers.contains(peer_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::store::{MemoryStore, RetentionPolicy};
+
+    const TOPIC: &str = "topic";
+
+    // 来自from的消息，主题为TOPIC
+    fn incoming(message_type: MessageType, from: &str) -> GossipMessage {
+        GossipMessage::new(message_type)
+            .with_topic(TOPIC.to_string())
+            .with_from(from.to_string())
+    }
+
+    // 使用手动时钟的节点，时间从1_000_000ms开始
+    fn test_node(config: GossipSubConfig) -> (GossipSubNode, ManualClock) {
+        let clock = ManualClock::new(1_000_000);
+        let node = GossipSubNode::with_clock("local".to_string(), config, Arc::new(clock.clone()));
+        (node, clock)
+    }
+
+    // 取出节点已发送的消息
+    fn drain(outbound: &mut UnboundedReceiver<(String, GossipMessage)>) -> Vec<(String, GossipMessage)> {
+        std::iter::from_fn(|| outbound.try_recv().ok()).collect()
+    }
+
+    // 订阅主题并连接一个peer，返回待发送消息的接收端
+    fn subscribed_node(
+        config: GossipSubConfig,
+    ) -> (GossipSubNode, ManualClock, UnboundedReceiver<(String, GossipMessage)>) {
+        let (mut node, clock) = test_node(config);
+        node.add_peer("peer".to_string(), "peer-addr".to_string());
+        node.subscribe(TOPIC.to_string());
+        let outbound = node.outbound_stream();
+        (node, clock, outbound)
+    }
+
+    fn sent_of_type(
+        outbound: &mut UnboundedReceiver<(String, GossipMessage)>,
+        message_type: MessageType,
+    ) -> Vec<GossipMessage> {
+        drain(outbound)
+            .into_iter()
+            .map(|(_, message)| message)
+            .filter(|message| message.message_type == message_type)
+            .collect()
+    }
+
+    #[test]
+    fn publish_rejects_oversized_message() {
+        let config = GossipSubConfig {
+            max_transmit_size: 128,
+            ..GossipSubConfig::default()
+        };
+        let (mut node, _clock, _outbound) = subscribed_node(config);
+
+        let result = node.publish(TOPIC, vec![0; 128]);
+
+        assert!(matches!(result, Err(GossipSubError::MessageTooLarge { max_size: 128, .. })));
+        assert!(node.message_cache.is_empty());
+    }
+
+    #[test]
+    fn oversized_message_is_rejected_and_penalized() {
+        let config = GossipSubConfig {
+            max_transmit_size: 128,
+            ..GossipSubConfig::default()
+        };
+        let (mut node, _clock, _outbound) = subscribed_node(config);
+        let message = incoming(MessageType::Publish, "peer").with_content(vec![0; 128]);
+        let message_id = message.message_id.clone();
+
+        let result = node.handle_message(message, "peer");
+
+        assert!(matches!(result, Err(GossipSubError::MessageTooLarge { max_size: 128, .. })));
+        assert!(!node.seen_messages.contains_key(&message_id));
+        assert!(!node.message_cache.contains_key(&message_id));
+        assert!(node.peer_score("peer") < 0.0);
+        assert_eq!(node.metrics.invalid, 1);
+    }
+
+    #[test]
+    fn ihave_and_iwant_are_capped_at_max_ihave_length() {
+        let config = GossipSubConfig {
+            max_ihave_length: 2,
+            ..GossipSubConfig::default()
+        };
+        let (mut node, _clock, mut outbound) = subscribed_node(config);
+        let ids: Vec<String> = (0..5).map(|i| format!("m{}", i)).collect();
+
+        let ihave = incoming(MessageType::IHave, "peer").with_message_ids(ids.clone());
+        node.handle_message(ihave, "peer").unwrap();
+        let iwants = sent_of_type(&mut outbound, MessageType::IWant);
+        assert_eq!(iwants.len(), 1);
+        assert_eq!(iwants[0].message_ids, ids[..2].to_vec());
+
+        for id in &ids {
+            let message = incoming(MessageType::Publish, "origin").with_message_id(id.clone());
+            node.message_cache.insert(id.clone(), message);
+        }
+        let iwant = incoming(MessageType::IWant, "peer").with_message_ids(ids.clone());
+        node.handle_message(iwant, "peer").unwrap();
+        assert_eq!(sent_of_type(&mut outbound, MessageType::Publish).len(), 2);
+    }
+
+    #[test]
+    fn broken_iwant_promise_is_penalized() {
+        let (mut node, clock, _outbound) = subscribed_node(GossipSubConfig::default());
+        let ihave = incoming(MessageType::IHave, "peer").with_message_ids(vec!["m0".to_string()]);
+        node.handle_message(ihave, "peer").unwrap();
+        assert!(node.iwant_promises["peer"].contains_key("m0"));
+
+        clock.advance(node.config.iwant_followup_time - 1);
+        node.gossip_heartbeat().unwrap();
+        assert_eq!(node.peer_score("peer"), 0.0);
+
+        clock.advance(1);
+        node.gossip_heartbeat().unwrap();
+        assert!(node.peer_score("peer") < 0.0);
+        assert!(!node.iwant_promises.contains_key("peer"));
+    }
+
+    #[test]
+    fn delivered_iwant_promise_is_kept() {
+        let (mut node, clock, _outbound) = subscribed_node(GossipSubConfig::default());
+        let ihave = incoming(MessageType::IHave, "peer").with_message_ids(vec!["m0".to_string()]);
+        node.handle_message(ihave, "peer").unwrap();
+
+        let mut message = incoming(MessageType::Publish, "peer");
+        message.message_id = "m0".to_string();
+        node.handle_message(message, "peer").unwrap();
+        clock.advance(node.config.iwant_followup_time);
+        node.gossip_heartbeat().unwrap();
+
+        assert!(node.iwant_promises.is_empty());
+        assert_eq!(node.peer_score("peer"), 0.0);
+    }
+
+    fn ihave(ids: &[&str]) -> GossipMessage {
+        incoming(MessageType::IHave, "peer")
+            .with_message_ids(ids.iter().map(|id| id.to_string()).collect())
+    }
+
+    #[test]
+    fn ihave_messages_are_limited_per_heartbeat() {
+        let config = GossipSubConfig {
+            max_ihave_messages: 1,
+            ..GossipSubConfig::default()
+        };
+        let (mut node, _clock, mut outbound) = subscribed_node(config);
+
+        node.handle_message(ihave(&["m0"]), "peer").unwrap();
+        node.handle_message(ihave(&["m1"]), "peer").unwrap();
+        assert_eq!(node.gossip_limit_stats.ignored_ihave, 1);
+        assert_eq!(sent_of_type(&mut outbound, MessageType::IWant).len(), 1);
+
+        node.gossip_heartbeat().unwrap();
+        node.handle_message(ihave(&["m2"]), "peer").unwrap();
+        assert_eq!(node.gossip_limit_stats.ignored_ihave, 1);
+        assert_eq!(sent_of_type(&mut outbound, MessageType::IWant).len(), 1);
+    }
+
+    #[test]
+    fn iwant_ids_are_limited_per_heartbeat() {
+        let config = GossipSubConfig {
+            max_iwant_ids: 2,
+            ..GossipSubConfig::default()
+        };
+        let (mut node, _clock, mut outbound) = subscribed_node(config);
+
+        node.handle_message(ihave(&["m0", "m1", "m2"]), "peer").unwrap();
+
+        let iwants = sent_of_type(&mut outbound, MessageType::IWant);
+        assert_eq!(iwants[0].message_ids, vec!["m0", "m1"]);
+        assert_eq!(node.gossip_limit_stats.ignored_iwant_ids, 1);
+    }
+
+    #[test]
+    fn iwant_retransmissions_are_limited() {
+        let config = GossipSubConfig {
+            gossip_retransmission: 1,
+            ..GossipSubConfig::default()
+        };
+        let (mut node, _clock, mut outbound) = subscribed_node(config);
+        let cached = incoming(MessageType::Publish, "origin").with_message_id("m0".to_string());
+        node.message_cache.insert("m0".to_string(), cached);
+
+        for _ in 0..2 {
+            let iwant = incoming(MessageType::IWant, "peer").with_message_ids(vec!["m0".to_string()]);
+            node.handle_message(iwant, "peer").unwrap();
+        }
+
+        assert_eq!(sent_of_type(&mut outbound, MessageType::Publish).len(), 1);
+        assert_eq!(node.gossip_limit_stats.ignored_retransmissions, 1);
+    }
+
+    #[test]
+    fn in_flight_iwant_is_deduplicated_and_retried() {
+        let config = GossipSubConfig {
+            max_iwant_in_flight: 1,
+            ..GossipSubConfig::default()
+        };
+        let (mut node, clock, mut outbound) = subscribed_node(config);
+        node.add_peer("other".to_string(), "other-addr".to_string());
+        drain(&mut outbound);
+
+        node.handle_message(ihave(&["m0"]), "peer").unwrap();
+        let from_other =
+            incoming(MessageType::IHave, "other").with_message_ids(vec!["m0".to_string()]);
+        node.handle_message(from_other, "other").unwrap();
+
+        let sent = drain(&mut outbound);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0, "peer");
+        assert_eq!(node.iwant_requests["m0"].advertisers, vec!["other"]);
+
+        // 第一个peer超时未送达，换宣告过的其他peer重试
+        clock.advance(node.config.iwant_followup_time);
+        node.gossip_heartbeat().unwrap();
+        let retried: Vec<String> = drain(&mut outbound)
+            .into_iter()
+            .filter(|(_, message)| message.message_type == MessageType::IWant)
+            .map(|(to, _)| to)
+            .collect();
+        assert_eq!(retried, vec!["other"]);
+    }
+
+    #[test]
+    fn iwant_retries_count_against_max_iwant_ids() {
+        let config = GossipSubConfig {
+            max_iwant_in_flight: 1,
+            max_iwant_ids: 1,
+            ..GossipSubConfig::default()
+        };
+        let (mut node, _clock, _outbound) = subscribed_node(config);
+        node.add_peer("other".to_string(), "other-addr".to_string());
+        node.handle_message(ihave(&["m0"]), "peer").unwrap();
+        node.gossip_heartbeat().unwrap();
+        node.handle_message(ihave(&["m1"]), "peer").unwrap();
+        let from_other = incoming(MessageType::IHave, "other")
+            .with_message_ids(vec!["m0".to_string(), "m1".to_string()]);
+        node.handle_message(from_other, "other").unwrap();
+
+        // 两个请求都超时，本周期只能向other重试其中一个
+        node.config.iwant_followup_time = 0;
+        node.gossip_heartbeat().unwrap();
+        let retried: Vec<&String> = ["m0", "m1"]
+            .iter()
+            .map(|id| &node.iwant_requests[*id])
+            .filter_map(|request| request.in_flight.keys().next())
+            .collect();
+        assert_eq!(retried, vec!["other"]);
+        assert_eq!(node.iasked_counts["other"], 1);
+
+        node.gossip_heartbeat().unwrap();
+        assert!(node.iwant_requests.values().all(|request| request.advertisers.is_empty()));
+    }
+
+    fn px(peer_id: &str) -> PeerInfo {
+        PeerInfo {
+            peer_id: peer_id.to_string(),
+            signed_peer_record: Some(format!("{}-addr", peer_id).into_bytes()),
+        }
+    }
+
+    #[test]
+    fn prune_carries_other_mesh_peers_as_px() {
+        for do_px in [true, false] {
+            let config = GossipSubConfig {
+                do_px,
+                ..GossipSubConfig::default()
+            };
+            let (mut node, _clock) = test_node(config);
+            for peer_id in ["a", "b", "c"] {
+                node.add_peer(peer_id.to_string(), format!("{}-addr", peer_id));
+            }
+            node.subscribe(TOPIC.to_string());
+            let mut outbound = node.outbound_stream();
+
+            let backoff = node.config.prune_backoff;
+            node.prune_peer_from_mesh(TOPIC, "a", backoff, PruneReason::MeshOverflow)
+                .unwrap();
+
+            let mut prunes = sent_of_type(&mut outbound, MessageType::Prune);
+            prunes[0].peers.sort_by(|x, y| x.peer_id.cmp(&y.peer_id));
+            let expected = if do_px { vec![px("b"), px("c")] } else { Vec::new() };
+            assert_eq!(prunes[0].peers, expected);
+        }
+    }
+
+    #[test]
+    fn px_peers_are_connected_when_mesh_is_low() {
+        let (mut node, _clock, _outbound) = subscribed_node(GossipSubConfig::default());
+        let prune = incoming(MessageType::Prune, "peer")
+            .with_peers(vec![px("x"), px("local"), px("peer")]);
+
+        node.handle_message(prune, "peer").unwrap();
+
+        assert_eq!(node.peers["x"].connection_info, "x-addr");
+        assert!(!node.peers.contains_key("local"));
+        assert_eq!(node.peers.len(), 2);
+    }
+
+    #[test]
+    fn px_from_low_scoring_peer_is_ignored() {
+        let (mut node, _clock, _outbound) = subscribed_node(GossipSubConfig::default());
+        node.add_peer_penalty("peer", 1.0);
+        let prune = incoming(MessageType::Prune, "peer").with_peers(vec![px("x")]);
+
+        node.handle_message(prune, "peer").unwrap();
+
+        assert!(!node.peers.contains_key("x"));
+    }
+
+    #[test]
+    fn px_is_ignored_when_mesh_is_not_low() {
+        let config = GossipSubConfig {
+            mesh_low: 1,
+            ..GossipSubConfig::default()
+        };
+        let (mut node, _clock) = test_node(config);
+        for peer_id in ["peer", "other"] {
+            node.add_peer(peer_id.to_string(), format!("{}-addr", peer_id));
+        }
+        node.subscribe(TOPIC.to_string());
+        let prune = incoming(MessageType::Prune, "peer").with_peers(vec![px("x")]);
+
+        node.handle_message(prune, "peer").unwrap();
+
+        assert!(!node.peers.contains_key("x"));
+    }
+
+    #[test]
+    fn prune_backoff_is_honoured_until_it_expires() {
+        let (mut node, clock, _outbound) = subscribed_node(GossipSubConfig::default());
+        assert!(node.is_in_mesh(TOPIC, "peer"));
+
+        let prune = incoming(MessageType::Prune, "peer").with_backoff(120_000);
+        node.handle_message(prune, "peer").unwrap();
+        assert!(!node.is_in_mesh(TOPIC, "peer"));
+
+        // 对方要求的退避长于本地graft_backoff，按对方的值执行
+        clock.advance(119_999);
+        node.gossip_heartbeat().unwrap();
+        assert!(!node.is_in_mesh(TOPIC, "peer"));
+
+        clock.advance(1);
+        node.gossip_heartbeat().unwrap();
+        assert!(node.is_in_mesh(TOPIC, "peer"));
+    }
+
+    #[test]
+    fn prune_backoff_uses_local_minimum() {
+        let (mut node, _clock, _outbound) = subscribed_node(GossipSubConfig::default());
+
+        let prune = incoming(MessageType::Prune, "peer").with_backoff(1);
+        node.handle_message(prune, "peer").unwrap();
+        assert_eq!(
+            node.graft_backoff[TOPIC]["peer"],
+            1_000_000 + node.config.graft_backoff
+        );
+    }
+
+    #[test]
+    fn remote_prune_backoff_is_clamped() {
+        let (mut node, clock, _outbound) = subscribed_node(GossipSubConfig::default());
+
+        let prune = incoming(MessageType::Prune, "peer").with_backoff(u64::MAX);
+        node.handle_message(prune, "peer").unwrap();
+
+        let max_backoff = node.config.max_prune_backoff;
+        assert_eq!(node.graft_backoff[TOPIC]["peer"], 1_000_000 + max_backoff);
+
+        clock.advance(max_backoff);
+        node.gossip_heartbeat().unwrap();
+        assert!(node.is_in_mesh(TOPIC, "peer"));
+    }
+
+    #[test]
+    fn unsubscribe_prunes_with_unsubscribe_backoff() {
+        let (mut node, _clock, _outbound) = subscribed_node(GossipSubConfig::default());
+
+        node.unsubscribe(TOPIC).unwrap();
+
+        assert!(!node.mesh.contains_key(TOPIC));
+        assert_eq!(
+            node.prune_backoff[TOPIC]["peer"],
+            1_000_000 + node.config.unsubscribe_backoff
+        );
+    }
+
+    #[test]
+    fn graft_during_backoff_is_penalized_and_pruned() {
+        let (mut node, _clock, mut outbound) = subscribed_node(GossipSubConfig::default());
+        node.prune_peer_from_mesh(TOPIC, "peer", node.config.prune_backoff, PruneReason::MeshOverflow)
+            .unwrap();
+        drain(&mut outbound);
+        let mut events = node.event_stream();
+
+        node.handle_message(incoming(MessageType::Graft, "peer"), "peer").unwrap();
+
+        assert!(!node.is_in_mesh(TOPIC, "peer"));
+        assert!(node.peer_score("peer") < 0.0);
+        let outbound = drain(&mut outbound);
+        assert_eq!(outbound.len(), 1);
+        let (to, reply) = &outbound[0];
+        assert_eq!(to, "peer");
+        assert_eq!(reply.message_type, MessageType::Prune);
+        assert_eq!(reply.backoff, Some(node.config.prune_backoff));
+        assert!(std::iter::from_fn(|| events.try_recv().ok()).any(|event| matches!(
+            event,
+            GossipSubEvent::GraftRejected { reason: GraftRejectReason::Backoff, .. }
+        )));
+    }
+
+    // mesh中只有a和b，c已连接但不在mesh中
+    fn mesh_of_two(config: GossipSubConfig) -> GossipSubNode {
+        let config = GossipSubConfig {
+            mesh_size: 2,
+            mesh_low: 2,
+            ..config
+        };
+        let (mut node, _clock) = test_node(config);
+        for peer_id in ["a", "b"] {
+            node.add_peer(peer_id.to_string(), format!("{}-addr", peer_id));
+        }
+        node.subscribe(TOPIC.to_string());
+        node.add_peer("c".to_string(), "c-addr".to_string());
+        node
+    }
+
+    #[test]
+    fn low_median_score_triggers_opportunistic_graft() {
+        let config = GossipSubConfig {
+            opportunistic_graft_ticks: 2,
+            ..GossipSubConfig::default()
+        };
+        let mut node = mesh_of_two(config);
+        node.add_peer_penalty("a", 2.0);
+        node.add_peer_penalty("b", 2.0);
+
+        // 只在每opportunistic_graft_ticks次心跳时检查
+        node.gossip_heartbeat().unwrap();
+        assert!(!node.is_in_mesh(TOPIC, "c"));
+
+        node.gossip_heartbeat().unwrap();
+        assert!(node.is_in_mesh(TOPIC, "c"));
+    }
+
+    #[test]
+    fn healthy_mesh_skips_opportunistic_graft() {
+        let config = GossipSubConfig {
+            opportunistic_graft_ticks: 1,
+            ..GossipSubConfig::default()
+        };
+        let mut node = mesh_of_two(config);
+
+        node.gossip_heartbeat().unwrap();
+
+        assert!(!node.is_in_mesh(TOPIC, "c"));
+    }
+
+    fn quota_config() -> GossipSubConfig {
+        GossipSubConfig {
+            mesh_size: 4,
+            mesh_low: 2,
+            mesh_high: 6,
+            mesh_outbound_min: 2,
+            ..GossipSubConfig::default()
+        }
+    }
+
+    #[test]
+    fn outbound_quota_grafts_outbound_peers() {
+        let (mut node, _clock) = test_node(quota_config());
+        for i in 0..4 {
+            node.add_inbound_peer(format!("in{}", i), format!("in{}-addr", i));
+        }
+        node.subscribe(TOPIC.to_string());
+        assert_eq!(node.outbound_mesh_count(TOPIC), 0);
+        for i in 0..3 {
+            node.add_peer(format!("out{}", i), format!("out{}-addr", i));
+        }
+        let mut events = node.event_stream();
+
+        node.gossip_heartbeat().unwrap();
+
+        assert_eq!(grafted_peers(&mut events, GraftReason::OutboundQuota).len(), 2);
+        assert_eq!(node.outbound_mesh_count(TOPIC), 2);
+        assert_eq!(node.get_mesh_size(TOPIC), 6);
+    }
+
+    #[test]
+    fn contract_mesh_keeps_outbound_quota() {
+        for seed in 0..20 {
+            let config = GossipSubConfig {
+                mesh_retain_score: 1,
+                rng_seed: Some(seed),
+                ..quota_config()
+            };
+            let (mut node, _clock) = test_node(config);
+            for i in 0..6 {
+                node.add_inbound_peer(format!("in{}", i), format!("in{}-addr", i));
+            }
+            for i in 0..2 {
+                node.add_peer(format!("out{}", i), format!("out{}-addr", i));
+            }
+            node.topics.insert(TOPIC.to_string());
+            node.mesh.insert(TOPIC.to_string(), node.peers.keys().cloned().collect());
+
+            node.contract_mesh(TOPIC).unwrap();
+
+            assert_eq!(node.get_mesh_size(TOPIC), 4);
+            assert_eq!(node.outbound_mesh_count(TOPIC), 2, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn full_mesh_still_accepts_graft_from_outbound_peer() {
+        let config = GossipSubConfig {
+            mesh_high: 4,
+            ..quota_config()
+        };
+        let (mut node, _clock) = test_node(config);
+        for i in 0..4 {
+            node.add_inbound_peer(format!("in{}", i), format!("in{}-addr", i));
+        }
+        node.subscribe(TOPIC.to_string());
+        node.add_peer("out".to_string(), "out-addr".to_string());
+        node.add_inbound_peer("late".to_string(), "late-addr".to_string());
+
+        node.handle_message(incoming(MessageType::Graft, "late"), "late").unwrap();
+        node.handle_message(incoming(MessageType::Graft, "out"), "out").unwrap();
+
+        assert!(!node.is_in_mesh(TOPIC, "late"));
+        assert!(node.is_in_mesh(TOPIC, "out"));
+    }
+
+    #[test]
+    fn contract_mesh_keeps_highest_scoring_peers() {
+        for seed in 0..20 {
+            let config = GossipSubConfig {
+                mesh_outbound_min: 0,
+                mesh_retain_score: 2,
+                rng_seed: Some(seed),
+                ..quota_config()
+            };
+            let (mut node, _clock) = test_node(config);
+            for i in 0..8 {
+                let peer_id = format!("p{}", i);
+                node.add_peer(peer_id.clone(), format!("{}-addr", peer_id));
+                // p6和p7没有惩罚，评分最高
+                if i < 6 {
+                    node.add_peer_penalty(&peer_id, 1.0);
+                }
+            }
+            node.topics.insert(TOPIC.to_string());
+            node.mesh.insert(TOPIC.to_string(), node.peers.keys().cloned().collect());
+            let mut events = node.event_stream();
+
+            node.contract_mesh(TOPIC).unwrap();
+
+            assert_eq!(node.get_mesh_size(TOPIC), 4);
+            assert!(node.is_in_mesh(TOPIC, "p6") && node.is_in_mesh(TOPIC, "p7"), "seed {}", seed);
+            let pruned = std::iter::from_fn(|| events.try_recv().ok())
+                .filter(|event| {
+                    matches!(
+                        event,
+                        GossipSubEvent::Pruned { reason: PruneReason::MeshOverflow, .. }
+                    )
+                })
+                .count();
+            assert_eq!(pruned, 4);
+        }
+    }
+
+    // 20个候选节点时订阅主题，返回排序后的初始mesh
+    fn initial_mesh(seed: u64) -> Vec<String> {
+        let config = GossipSubConfig {
+            rng_seed: Some(seed),
+            ..GossipSubConfig::default()
+        };
+        let (mut node, _clock) = test_node(config);
+        for i in 0..20 {
+            node.add_peer(format!("p{:02}", i), format!("p{:02}-addr", i));
+        }
+        node.subscribe(TOPIC.to_string());
+        let mut mesh: Vec<String> = node.mesh[TOPIC].iter().cloned().collect();
+        mesh.sort();
+        mesh
+    }
+
+    #[test]
+    fn seeded_rng_makes_peer_selection_reproducible() {
+        assert_eq!(initial_mesh(7), initial_mesh(7));
+
+        let distinct: HashSet<Vec<String>> = (0..10).map(initial_mesh).collect();
+        assert!(distinct.len() > 1);
+    }
+
+    // mesh中有8个节点，PRUNE p0时随机挑选的PX节点
+    fn px_selection(seed: u64) -> Vec<String> {
+        let config = GossipSubConfig {
+            prune_peers: 3,
+            rng_seed: Some(seed),
+            ..GossipSubConfig::default()
+        };
+        let (mut node, _clock) = test_node(config);
+        for i in 0..8 {
+            node.add_peer(format!("p{}", i), format!("p{}-addr", i));
+        }
+        node.subscribe(TOPIC.to_string());
+        node.px_peers(TOPIC, "p0").into_iter().map(|peer| peer.peer_id).collect()
+    }
+
+    #[test]
+    fn px_peers_are_chosen_randomly() {
+        assert_eq!(px_selection(3), px_selection(3));
+        assert_eq!(px_selection(3).len(), 3);
+        assert!(!px_selection(3).contains(&"p0".to_string()));
+
+        let distinct: HashSet<Vec<String>> = (0..10).map(px_selection).collect();
+        assert!(distinct.len() > 1);
+    }
+
+    // mesh中有m0和m1，另有subscribed个订阅了主题的节点和两个未订阅的节点
+    fn gossip_node(
+        config: GossipSubConfig,
+        subscribed: usize,
+    ) -> (GossipSubNode, UnboundedReceiver<(String, GossipMessage)>) {
+        let config = GossipSubConfig {
+            mesh_size: 2,
+            mesh_low: 2,
+            mesh_outbound_min: 1,
+            ..config
+        };
+        let (mut node, _clock) = test_node(config);
+        for peer_id in ["m0", "m1"] {
+            node.add_peer(peer_id.to_string(), format!("{}-addr", peer_id));
+        }
+        node.subscribe(TOPIC.to_string());
+        for i in 0..subscribed {
+            let peer_id = format!("s{}", i);
+            node.add_peer(peer_id.clone(), format!("{}-addr", peer_id));
+            node.handle_message(incoming(MessageType::Subscribe, &peer_id), &peer_id).unwrap();
+        }
+        for peer_id in ["u0", "u1"] {
+            node.add_peer(peer_id.to_string(), format!("{}-addr", peer_id));
+        }
+        node.publish(TOPIC, b"hello".to_vec()).unwrap();
+        let outbound = node.outbound_stream();
+        (node, outbound)
+    }
+
+    fn ihave_targets(outbound: &mut UnboundedReceiver<(String, GossipMessage)>) -> Vec<String> {
+        let mut targets: Vec<String> = drain(outbound)
+            .into_iter()
+            .filter(|(_, message)| message.message_type == MessageType::IHave)
+            .map(|(peer_id, _)| peer_id)
+            .collect();
+        targets.sort();
+        targets
+    }
+
+    #[test]
+    fn gossip_targets_scale_with_gossip_factor() {
+        let config = GossipSubConfig {
+            d_lazy: 2,
+            gossip_factor: 0.25,
+            ..GossipSubConfig::default()
+        };
+        let (mut node, mut outbound) = gossip_node(config, 12);
+
+        node.gossip_heartbeat().unwrap();
+
+        let targets = ihave_targets(&mut outbound);
+        assert_eq!(targets.len(), 3);
+        assert!(targets.iter().all(|peer_id| peer_id.starts_with('s')));
+    }
+
+    #[test]
+    fn gossip_targets_are_at_least_d_lazy() {
+        let config = GossipSubConfig {
+            d_lazy: 3,
+            gossip_factor: 0.25,
+            ..GossipSubConfig::default()
+        };
+        let (mut node, mut outbound) = gossip_node(config, 4);
+
+        node.gossip_heartbeat().unwrap();
+
+        assert_eq!(ihave_targets(&mut outbound).len(), 3);
+    }
+
+    #[test]
+    fn subscription_announcements_track_peer_topics() {
+        let (mut node, _clock, _outbound) = subscribed_node(GossipSubConfig::default());
+
+        node.handle_message(incoming(MessageType::Subscribe, "peer"), "peer").unwrap();
+        assert!(node.peer_topics["peer"].contains(TOPIC));
+
+        node.handle_message(incoming(MessageType::Unsubscribe, "peer"), "peer").unwrap();
+        assert!(!node.peer_topics["peer"].contains(TOPIC));
+    }
+
+    fn direct_config() -> GossipSubConfig {
+        let mut config = GossipSubConfig {
+            direct_connect_ticks: 1,
+            ..GossipSubConfig::default()
+        };
+        config
+            .direct_peers
+            .insert("relay".to_string(), "relay-addr".to_string());
+        config
+    }
+
+    #[test]
+    fn direct_peers_are_kept_out_of_the_mesh() {
+        let (node, _clock, _outbound) = subscribed_node(direct_config());
+
+        assert!(node.peers.contains_key("relay"));
+        assert!(node.is_in_mesh(TOPIC, "peer"));
+        assert!(!node.is_in_mesh(TOPIC, "relay"));
+    }
+
+    #[test]
+    fn direct_peers_receive_every_message_and_no_gossip() {
+        let (mut node, _clock, mut outbound) = subscribed_node(direct_config());
+        node.handle_message(incoming(MessageType::Subscribe, "relay"), "relay").unwrap();
+        assert!(!node.is_in_mesh(TOPIC, "relay"));
+
+        node.publish(TOPIC, b"hello".to_vec()).unwrap();
+        let published: Vec<String> = drain(&mut outbound)
+            .into_iter()
+            .filter(|(_, message)| message.message_type == MessageType::Publish)
+            .map(|(peer_id, _)| peer_id)
+            .collect();
+        assert!(published.contains(&"relay".to_string()));
+
+        let message = incoming(MessageType::Publish, "peer").with_content(b"hi".to_vec());
+        node.handle_message(message, "peer").unwrap();
+        let forwarded: Vec<String> = drain(&mut outbound)
+            .into_iter()
+            .filter(|(_, message)| message.message_type == MessageType::Publish)
+            .map(|(peer_id, _)| peer_id)
+            .collect();
+        assert_eq!(forwarded, vec!["relay".to_string()]);
+
+        // 直连节点已收到全部消息，不需要IHAVE
+        node.gossip_heartbeat().unwrap();
+        assert!(sent_of_type(&mut outbound, MessageType::IHave).is_empty());
+    }
+
+    #[test]
+    fn graft_from_direct_peer_is_rejected() {
+        let (mut node, _clock, mut outbound) = subscribed_node(direct_config());
+        let mut events = node.event_stream();
+
+        node.handle_message(incoming(MessageType::Graft, "relay"), "relay").unwrap();
+
+        assert!(!node.is_in_mesh(TOPIC, "relay"));
+        assert!(node.peer_score("relay") < 0.0);
+        let prunes = sent_of_type(&mut outbound, MessageType::Prune);
+        assert_eq!(prunes.len(), 1);
+        assert_eq!(prunes[0].to.as_deref(), Some("relay"));
+        assert!(std::iter::from_fn(|| events.try_recv().ok()).any(|event| matches!(
+            event,
+            GossipSubEvent::GraftRejected { reason: GraftRejectReason::DirectPeer, .. }
+        )));
+    }
+
+    #[test]
+    fn disconnected_direct_peers_are_reconnected() {
+        let (mut node, _clock) = test_node(direct_config());
+        assert!(node.peers.contains_key("relay"));
+        node.remove_peer("relay");
+
+        node.gossip_heartbeat().unwrap();
+
+        assert_eq!(node.peers["relay"].connection_info, "relay-addr");
+    }
+
+    #[test]
+    fn remove_peer_clears_its_gossip_state() {
+        let config = GossipSubConfig {
+            max_iwant_in_flight: 1,
+            ..GossipSubConfig::default()
+        };
+        let (mut node, _clock, _outbound) = subscribed_node(config);
+        node.add_peer("other".to_string(), "other-addr".to_string());
+        node.handle_message(ihave(&["m0"]), "peer").unwrap();
+        node.handle_message(ihave(&["m0"]), "other").unwrap();
+        assert!(node.iwant_requests["m0"].in_flight.contains_key("peer"));
+
+        node.remove_peer("peer");
+
+        assert!(!node.iwant_promises.contains_key("peer"));
+        assert!(!node.ihave_counts.contains_key("peer"));
+        assert!(!node.iasked_counts.contains_key("peer"));
+        assert!(node.iwant_requests["m0"].in_flight.is_empty());
+        assert_eq!(node.iwant_requests["m0"].advertisers, vec!["other".to_string()]);
+
+        // 让出的名额在下个心跳由其他宣告者重试
+        node.gossip_heartbeat().unwrap();
+        assert!(node.iwant_requests["m0"].in_flight.contains_key("other"));
+    }
+
+    #[test]
+    fn publish_without_peers_is_cached() {
+        let (mut node, _clock) = test_node(GossipSubConfig::default());
+        node.subscribe(TOPIC.to_string());
+
+        let message_id = node.publish(TOPIC, b"hello".to_vec()).unwrap();
+
+        assert!(node.message_cache.contains_key(&message_id));
+        assert!(node.seen_messages.contains_key(&message_id));
+    }
+
+    #[test]
+    fn publish_rejects_unsubscribed_and_oversized() {
+        let config = GossipSubConfig {
+            max_transmit_size: 64,
+            ..GossipSubConfig::default()
+        };
+        let (mut node, _clock) = test_node(config);
+        assert_eq!(
+            node.publish(TOPIC, Vec::new()),
+            Err(GossipSubError::NotSubscribed(TOPIC.to_string()))
+        );
+
+        node.subscribe(TOPIC.to_string());
+        assert!(matches!(
+            node.publish(TOPIC, vec![0; 64]),
+            Err(GossipSubError::MessageTooLarge { max_size: 64, .. })
+        ));
+    }
+
+    #[test]
+    fn duplicate_message_is_ignored_not_failed() {
+        let (mut node, _clock, _outbound) = subscribed_node(GossipSubConfig::default());
+        let mut events = node.event_stream();
+        let message = incoming(MessageType::Publish, "peer").with_content(b"hi".to_vec());
+
+        node.handle_message(message.clone(), "peer").unwrap();
+        assert_eq!(node.handle_message(message.clone(), "peer"), Ok(()));
+        assert_eq!(node.message_cache.len(), 1);
+        assert_eq!(node.metrics.duplicates, 1);
+        let duplicate = std::iter::from_fn(|| events.try_recv().ok()).any(|event| {
+            matches!(
+                event,
+                GossipSubEvent::MessageDuplicate { message_id, .. } if message_id == message.message_id
+            )
+        });
+        assert!(duplicate);
+    }
+
+    // 收集tracing输出，代替标准输出
+    #[derive(Clone, Default)]
+    struct CapturedLogs(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn logs_carry_structured_fields_and_spans() {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::TRACE)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+
+        let message = incoming(MessageType::Publish, "peer").with_content(b"hi".to_vec());
+        let message_id = message.message_id.clone();
+        tracing::subscriber::with_default(subscriber, || {
+            let (mut node, _clock, _outbound) = subscribed_node(GossipSubConfig::default());
+            node.handle_message(message, "peer").unwrap();
+            node.gossip_heartbeat().unwrap();
+        });
+
+        let output = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("heartbeat{node_id=local tick=1}"));
+        assert!(output.contains(&format!(
+            "handle_message{{node_id=local peer=\"peer\" message_type=Publish message_id={}}}",
+            message_id
+        )));
+    }
+
+    // 取出指定原因的Grafted事件中的节点，按名称排序
+    fn grafted_peers(
+        events: &mut UnboundedReceiver<GossipSubEvent>,
+        reason: GraftReason,
+    ) -> Vec<String> {
+        let mut peers: Vec<String> = std::iter::from_fn(|| events.try_recv().ok())
+            .filter_map(|event| match event {
+                GossipSubEvent::Grafted { peer_id, reason: r, .. } if r == reason => Some(peer_id),
+                _ => None,
+            })
+            .collect();
+        peers.sort();
+        peers
+    }
+
+    #[test]
+    fn initialize_mesh_sends_graft_and_emits_grafted() {
+        let (mut node, _clock) = test_node(GossipSubConfig::default());
+        node.add_peer("a".to_string(), "a-addr".to_string());
+        node.add_peer("b".to_string(), "b-addr".to_string());
+        let mut outbound = node.outbound_stream();
+        let mut events = node.event_stream();
+
+        node.subscribe(TOPIC.to_string());
+
+        assert_eq!(grafted_peers(&mut events, GraftReason::MeshInit), vec!["a", "b"]);
+        let mut grafts: Vec<String> = drain(&mut outbound)
+            .into_iter()
+            .filter(|(_, message)| message.message_type == MessageType::Graft)
+            .map(|(peer_id, _)| peer_id)
+            .collect();
+        grafts.sort();
+        assert_eq!(grafts, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn initialize_mesh_prefers_fanout_and_subscribed_peers() {
+        let config = GossipSubConfig {
+            mesh_size: 2,
+            ..GossipSubConfig::default()
+        };
+        let (mut node, _clock) = test_node(config);
+        for peer_id in ["a", "b", "c", "d", "e"] {
+            node.add_peer(peer_id.to_string(), format!("{}-addr", peer_id));
+        }
+        node.fanout
+            .insert(TOPIC.to_string(), HashSet::from(["a".to_string()]));
+        node.handle_message(incoming(MessageType::Subscribe, "b"), "b").unwrap();
+        node.handle_message(incoming(MessageType::Subscribe, "c"), "c").unwrap();
+        // c在GRAFT退避期内，不应被选中
+        node.graft_backoff
+            .entry(TOPIC.to_string())
+            .or_default()
+            .insert("c".to_string(), u64::MAX);
+
+        node.subscribe(TOPIC.to_string());
+
+        let mut mesh: Vec<&String> = node.mesh[TOPIC].iter().collect();
+        mesh.sort();
+        assert_eq!(mesh, vec!["a", "b"]);
+        assert!(!node.fanout.contains_key(TOPIC));
+    }
+
+    #[test]
+    fn remove_peer_emits_pruned() {
+        let (mut node, _clock, _outbound) = subscribed_node(GossipSubConfig::default());
+        assert!(node.is_in_mesh(TOPIC, "peer"));
+        let mut events = node.event_stream();
+
+        node.remove_peer("peer");
+
+        assert!(!node.is_in_mesh(TOPIC, "peer"));
+        let pruned = std::iter::from_fn(|| events.try_recv().ok()).any(|event| {
+            matches!(
+                event,
+                GossipSubEvent::Pruned { peer_id, reason: PruneReason::PeerDisconnected, .. }
+                    if peer_id == "peer"
+            )
+        });
+        assert!(pruned);
+    }
+
+    #[test]
+    fn heartbeat_expires_message_cache_and_backoffs() {
+        let (mut node, clock) = test_node(GossipSubConfig::default());
+        node.subscribe(TOPIC.to_string());
+        let message_id = node.publish(TOPIC, b"hello".to_vec()).unwrap();
+        node.prune_backoff
+            .entry(TOPIC.to_string())
+            .or_default()
+            .insert("peer".to_string(), clock.now_millis() + 1000);
+
+        clock.advance(node.config.message_cache_ttl);
+        node.gossip_heartbeat().unwrap();
+        assert!(!node.message_cache.contains_key(&message_id));
+        assert!(node.seen_messages.contains_key(&message_id));
+        assert!(node.prune_backoff.is_empty());
+
+        clock.advance(node.config.seen_ttl);
+        node.gossip_heartbeat().unwrap();
+        assert!(!node.seen_messages.contains_key(&message_id));
+    }
+
+    #[test]
+    fn wall_clock_rollback_does_not_disturb_expiry() {
+        let (mut node, clock, _outbound) = subscribed_node(GossipSubConfig::default());
+        let message_id = node.publish(TOPIC, b"hello".to_vec()).unwrap();
+        node.handle_message(ihave(&["m1"]), "peer").unwrap();
+
+        // 墙上时间回拨不影响按单调时间计算的过期
+        clock.set(0);
+        node.gossip_heartbeat().unwrap();
+        assert!(node.message_cache.contains_key(&message_id));
+        assert!(node.iwant_requests.contains_key("m1"));
+
+        clock.advance(node.config.message_cache_ttl);
+        node.gossip_heartbeat().unwrap();
+        assert!(!node.message_cache.contains_key(&message_id));
+        assert!(!node.iwant_requests.contains_key("m1"));
+    }
+
+    #[test]
+    fn messages_are_stamped_with_the_injected_clock() {
+        let (mut node, clock, mut outbound) = subscribed_node(GossipSubConfig::default());
+        clock.set(42_000);
+
+        node.publish(TOPIC, b"hello".to_vec()).unwrap();
+
+        let published = sent_of_type(&mut outbound, MessageType::Publish);
+        assert_eq!(published[0].timestamp, 42_000);
+    }
+
+    #[test]
+    fn seen_messages_expire_after_seen_ttl() {
+        let config = GossipSubConfig {
+            seen_ttl: 10_000,
+            ..GossipSubConfig::default()
+        };
+        let (mut node, clock, _outbound) = subscribed_node(config);
+        let message = incoming(MessageType::Publish, "peer");
+        let message_id = message.message_id.clone();
+        node.handle_message(message, "peer").unwrap();
+
+        clock.advance(9_999);
+        node.cleanup_message_cache();
+        assert!(node.seen_messages.contains_key(&message_id));
+
+        clock.advance(1);
+        node.cleanup_message_cache();
+        assert!(!node.seen_messages.contains_key(&message_id));
+    }
+
+    #[test]
+    fn iwant_requests_expire_after_message_cache_ttl() {
+        let config = GossipSubConfig {
+            message_cache_ttl: 5_000,
+            ..GossipSubConfig::default()
+        };
+        let (mut node, clock, _outbound) = subscribed_node(config);
+        node.handle_message(ihave(&["m1"]), "peer").unwrap();
+        assert!(node.iwant_requests.contains_key("m1"));
+
+        clock.advance(4_999);
+        node.cleanup_expired_iwant_requests();
+        assert!(node.iwant_requests.contains_key("m1"));
+
+        clock.advance(1);
+        node.cleanup_expired_iwant_requests();
+        assert!(!node.iwant_requests.contains_key("m1"));
+    }
+
+    #[test]
+    fn graylisted_peer_messages_are_ignored() {
+        let (mut node, _clock, _outbound) = subscribed_node(GossipSubConfig::default());
+        // 惩罚3次后评分为-90，低于默认灰名单阈值-80
+        node.add_peer_penalty("peer", 3.0);
+
+        let message = incoming(MessageType::Publish, "peer");
+        let message_id = message.message_id.clone();
+        node.handle_message(message, "peer").unwrap();
+
+        assert!(!node.seen_messages.contains_key(&message_id));
+    }
+
+    #[test]
+    fn fanout_skips_peers_below_publish_threshold() {
+        let (mut node, _clock) = test_node(GossipSubConfig::default());
+        node.subscribe(TOPIC.to_string());
+        for peer_id in ["peer", "low"] {
+            node.add_peer(peer_id.to_string(), format!("{}-addr", peer_id));
+        }
+        let mut outbound = node.outbound_stream();
+        // 评分-62.5，低于发布阈值-50但高于灰名单阈值
+        node.add_peer_penalty("low", 2.5);
+
+        node.publish(TOPIC, b"hello".to_vec()).unwrap();
+
+        let targets: Vec<String> = drain(&mut outbound)
+            .into_iter()
+            .filter(|(_, message)| message.message_type == MessageType::Publish)
+            .map(|(to, _)| to)
+            .collect();
+        assert_eq!(targets, vec!["peer"]);
+    }
+
+    // 消息存储中预先写入count条消息，时间戳从1000开始递增
+    fn with_history(node: &mut GossipSubNode, count: u64) -> Vec<GossipMessage> {
+        let mut store = MemoryStore::new(RetentionPolicy {
+            max_age: None,
+            max_messages: None,
+            max_bytes: None,
+        });
+        let messages: Vec<GossipMessage> = (0..count)
+            .map(|i| {
+                incoming(MessageType::Publish, "origin")
+                    .with_message_id(format!("m{}", i))
+                    .with_content(vec![0; 16])
+                    .with_timestamp(1000 + i)
+            })
+            .collect();
+        for message in &messages {
+            store.append(message).unwrap();
+        }
+        node.set_message_store(Box::new(store));
+        messages
+    }
+
+    fn page_ids(page: &[GossipMessage]) -> Vec<&str> {
+        page.iter().map(|m| m.message_id.as_str()).collect()
+    }
+
+    #[test]
+    fn history_page_follows_after_cursor() {
+        let config = GossipSubConfig {
+            history_page_size: 2,
+            ..GossipSubConfig::default()
+        };
+        let (mut node, _clock) = test_node(config);
+        with_history(&mut node, 5);
+        let query = HistoryQuery {
+            limit: 10,
+            ..HistoryQuery::default()
+        };
+
+        let (page, next) = node.history_page(TOPIC, &query, 0);
+        assert_eq!(page_ids(&page), vec!["m0", "m1"]);
+        let next = next.unwrap();
+        assert_eq!(next.after.as_deref(), Some("m1"));
+        assert_eq!(next.limit, 10);
+
+        let (page, next) = node.history_page(TOPIC, &next, 0);
+        assert_eq!(page_ids(&page), vec!["m2", "m3"]);
+
+        let (page, next) = node.history_page(TOPIC, &next.unwrap(), 0);
+        assert_eq!(page_ids(&page), vec!["m4"]);
+        assert!(next.is_none());
+    }
+
+    #[test]
+    fn inverted_history_range_is_rejected_and_penalized() {
+        let (mut node, _clock, mut outbound) = subscribed_node(GossipSubConfig::default());
+        with_history(&mut node, 3);
+        let request = incoming(MessageType::HistoryRequest, "peer").with_history(HistoryQuery {
+            from: Some(2000),
+            to: Some(1000),
+            limit: 10,
+            ..HistoryQuery::default()
+        });
+
+        let result = node.handle_message(request, "peer");
+
+        assert!(matches!(result, Err(GossipSubError::ValidationFailed(_))));
+        assert!(node.peer_score("peer") < 0.0);
+        assert!(sent_of_type(&mut outbound, MessageType::HistoryResponse).is_empty());
+    }
+
+    #[test]
+    fn history_page_truncates_to_transmit_size() {
+        let (mut node, _clock) = test_node(GossipSubConfig::default());
+        let messages = with_history(&mut node, 3);
+        // 只够放下一条消息和下一页的查询
+        let next_size = "m0".len() + 3 * std::mem::size_of::<u64>();
+        node.config.max_transmit_size = messages[0].encoded_len() + next_size;
+        let query = HistoryQuery {
+            limit: 10,
+            ..HistoryQuery::default()
+        };
+
+        let (page, next) = node.history_page(TOPIC, &query, 0);
+        assert_eq!(page_ids(&page), vec!["m0"]);
+        assert_eq!(next.unwrap().after.as_deref(), Some("m0"));
+
+        let (page, next) = node.history_page(TOPIC, &query, messages[0].encoded_len());
+        assert!(page.is_empty());
+        assert_eq!(next.unwrap().after.as_deref(), Some("m0"));
+    }
+
+    #[test]
+    fn history_response_is_checked_and_stored_only() {
+        let config = GossipSubConfig {
+            history_sync_messages: 0,
+            ..GossipSubConfig::default()
+        };
+        let (mut node, clock) = test_node(config);
+        with_history(&mut node, 0);
+        node.add_peer("peer".to_string(), "peer-addr".to_string());
+        node.subscribe(TOPIC.to_string());
+        let now = clock.unix_millis();
+        let query = HistoryQuery {
+            from: Some(now - 10_000),
+            limit: 3,
+            ..HistoryQuery::default()
+        };
+        let request_id = node.request_history("peer", TOPIC, query).unwrap();
+        let mut events = node.event_stream();
+
+        let history = |id: &str, timestamp: u64| {
+            incoming(MessageType::Publish, "origin")
+                .with_message_id(id.to_string())
+                .with_timestamp(timestamp)
+        };
+        let response = incoming(MessageType::HistoryResponse, "peer")
+            .with_request_id(request_id)
+            .with_messages(vec![
+                history("valid", now - 5_000),
+                history("too-old", now - 20_000),
+                history("future", now + HISTORY_CLOCK_SKEW + 1),
+                history("over-limit", now - 1_000),
+            ]);
+        node.handle_message(response, "peer").unwrap();
+
+        let store = node.message_store().unwrap();
+        assert!(store.get("valid").is_some());
+        assert_eq!(store.len(), 1);
+        assert!(node.message_cache.is_empty());
+        assert!(node.peer_score("peer") < 0.0);
+        let received = std::iter::from_fn(|| events.try_recv().ok()).find_map(|event| match event {
+            GossipSubEvent::HistoryReceived { message_ids, .. } => Some(message_ids),
+            _ => None,
+        });
+        assert_eq!(received, Some(vec!["valid".to_string()]));
     }
 }
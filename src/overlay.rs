@@ -0,0 +1,351 @@
+use crate::error::GossipSubError;
+use crate::node::GossipSubNode;
+use crate::simulator::Simulator;
+use crate::types::ConnectionDirection;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+// 导出选项
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OverlayOptions {
+    pub include_scores: bool, // 在边上附带from节点看到的对方评分
+}
+
+// 边的类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EdgeKind {
+    Connection, // 传输层连接
+    Mesh,       // 主题mesh
+    Fanout,     // 未订阅主题的fanout
+    Direct,     // 直连节点
+}
+
+// 拓扑中的节点
+#[derive(Debug, Clone, Serialize)]
+pub struct OverlayNode {
+    pub id: String,
+    pub topics: Vec<String>, // 已知的订阅主题
+}
+
+// 拓扑中的有向边，从from节点的视角记录
+#[derive(Debug, Clone, Serialize)]
+pub struct OverlayEdge {
+    pub from: String,
+    pub to: String,
+    pub kind: EdgeKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>, // mesh和fanout边所属的主题
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub direction: Option<ConnectionDirection>, // 连接边的方向
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<f64>, // from节点给to节点的评分
+}
+
+// overlay网络的快照
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct OverlaySnapshot {
+    pub nodes: Vec<OverlayNode>,
+    pub edges: Vec<OverlayEdge>,
+}
+
+impl OverlaySnapshot {
+    // 导出为Graphviz DOT格式
+    pub fn to_dot(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "digraph gossipsub {{");
+        let _ = writeln!(out, "  node [shape=ellipse];");
+
+        for node in &self.nodes {
+            // 各部分先转义，再用DOT的\n换行拼接，避免换行符被当作反斜杠转义
+            let id = escape_dot(&node.id);
+            let label = if node.topics.is_empty() {
+                id.clone()
+            } else {
+                format!("{}\\n[{}]", id, escape_dot(&node.topics.join(", ")))
+            };
+            let _ = writeln!(out, "  \"{}\" [label=\"{}\"];", id, label);
+        }
+
+        for edge in &self.edges {
+            let mut label = edge.topic.clone().unwrap_or_default();
+            if let Some(score) = edge.score {
+                if !label.is_empty() {
+                    label.push(' ');
+                }
+                let _ = write!(label, "({:.2})", score);
+            }
+            let style = match edge.kind {
+                EdgeKind::Connection => "color=gray, style=dotted",
+                EdgeKind::Mesh => "color=blue",
+                EdgeKind::Fanout => "color=orange, style=dashed",
+                EdgeKind::Direct => "color=red, style=bold",
+            };
+            let _ = writeln!(
+                out,
+                "  \"{}\" -> \"{}\" [{}, label=\"{}\"];",
+                escape_dot(&edge.from),
+                escape_dot(&edge.to),
+                style,
+                escape_dot(&label)
+            );
+        }
+
+        let _ = writeln!(out, "}}");
+        out
+    }
+
+    // 导出为JSON
+    pub fn to_json(&self) -> Result<String, GossipSubError> {
+        serde_json::to_string_pretty(self).map_err(|e| GossipSubError::Codec(e.to_string()))
+    }
+
+    // 按固定顺序排列，便于比较两次导出的结果
+    fn sort(&mut self) {
+        self.nodes.sort_by(|a, b| a.id.cmp(&b.id));
+        self.edges.sort_by(|a, b| {
+            (&a.from, &a.to, a.kind, &a.topic).cmp(&(&b.from, &b.to, b.kind, &b.topic))
+        });
+    }
+}
+
+// 转义DOT字符串，先转义反斜杠再转义引号
+fn escape_dot(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl GossipSubNode {
+    // 本节点视角的overlay：连接、mesh、fanout和直连节点
+    pub fn overlay(&self, options: OverlayOptions) -> OverlaySnapshot {
+        let score = |peer_id: &str| options.include_scores.then(|| self.peer_score(peer_id));
+        let edge = |to: &str, kind: EdgeKind, topic: Option<&String>| OverlayEdge {
+            from: self.node_id.clone(),
+            to: to.to_string(),
+            kind,
+            topic: topic.cloned(),
+            direction: None,
+            score: score(to),
+        };
+
+        let mut snapshot = OverlaySnapshot::default();
+        let mut topics: Vec<String> = self.topics.iter().cloned().collect();
+        topics.sort();
+        snapshot.nodes.push(OverlayNode {
+            id: self.node_id.clone(),
+            topics,
+        });
+
+        for (peer_id, peer) in &self.peers {
+            let mut topics: Vec<String> = self
+                .peer_topics
+                .get(peer_id)
+                .map(|topics| topics.iter().cloned().collect())
+                .unwrap_or_default();
+            topics.sort();
+            snapshot.nodes.push(OverlayNode {
+                id: peer_id.clone(),
+                topics,
+            });
+
+            let kind = if self.is_direct_peer(peer_id) {
+                EdgeKind::Direct
+            } else {
+                EdgeKind::Connection
+            };
+            snapshot.edges.push(OverlayEdge {
+                direction: Some(peer.direction),
+                ..edge(peer_id, kind, None)
+            });
+        }
+
+        for (kind, groups) in [(EdgeKind::Mesh, &self.mesh), (EdgeKind::Fanout, &self.fanout)] {
+            for (topic, peers) in groups {
+                for peer_id in peers {
+                    snapshot.edges.push(edge(peer_id, kind, Some(topic)));
+                }
+            }
+        }
+
+        snapshot.sort();
+        snapshot
+    }
+
+    pub fn export_dot(&self, options: OverlayOptions) -> String {
+        self.overlay(options).to_dot()
+    }
+
+    pub fn export_json(&self, options: OverlayOptions) -> Result<String, GossipSubError> {
+        self.overlay(options).to_json()
+    }
+}
+
+impl Simulator {
+    // 合并所有节点视角的overlay，每条连接只保留发起方一侧
+    pub fn overlay(&self, options: OverlayOptions) -> OverlaySnapshot {
+        let mut nodes = BTreeMap::new();
+        let mut snapshot = OverlaySnapshot::default();
+
+        for node in self.nodes() {
+            let view = node.overlay(options);
+            for overlay_node in view.nodes {
+                // 模拟器中的节点以自己的订阅为准，其余节点使用邻居看到的订阅
+                if overlay_node.id == node.node_id || !nodes.contains_key(&overlay_node.id) {
+                    nodes.insert(overlay_node.id.clone(), overlay_node);
+                }
+            }
+            snapshot.edges.extend(view.edges.into_iter().filter(|edge| {
+                !matches!(edge.kind, EdgeKind::Connection | EdgeKind::Direct)
+                    || edge.direction != Some(ConnectionDirection::Inbound)
+            }));
+        }
+
+        snapshot.nodes = nodes.into_values().collect();
+        snapshot.sort();
+        snapshot
+    }
+
+    pub fn export_dot(&self, options: OverlayOptions) -> String {
+        self.overlay(options).to_dot()
+    }
+
+    pub fn export_json(&self, options: OverlayOptions) -> Result<String, GossipSubError> {
+        self.overlay(options).to_json()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::message::GossipMessage;
+    use crate::simulator::SimulatorConfig;
+    use crate::types::{GossipSubConfig, MessageType};
+    use serde_json::Value;
+    use std::sync::Arc;
+
+    // local订阅topic，mesh中有a(出站)和b(入站)，relay是直连节点，other主题的fanout中有a
+    fn local_node() -> GossipSubNode {
+        let mut config = GossipSubConfig::default();
+        config
+            .direct_peers
+            .insert("relay".to_string(), "relay-addr".to_string());
+        let mut node = GossipSubNode::with_clock(
+            "local".to_string(),
+            config,
+            Arc::new(ManualClock::new(1_000_000)),
+        );
+        let _outbound = node.outbound_stream();
+        node.add_peer("a".to_string(), "a-addr".to_string());
+        node.add_inbound_peer("b".to_string(), "b-addr".to_string());
+        let subscribe = GossipMessage::new(MessageType::Subscribe)
+            .with_topic("topic".to_string())
+            .with_from("b".to_string());
+        node.handle_message(subscribe, "b").unwrap();
+        node.subscribe("topic".to_string());
+        node.fanout
+            .insert("other".to_string(), ["a".to_string()].into_iter().collect());
+        node
+    }
+
+    fn edges(snapshot: &OverlaySnapshot) -> Vec<(&str, EdgeKind, Option<&str>)> {
+        snapshot
+            .edges
+            .iter()
+            .map(|edge| (edge.to.as_str(), edge.kind, edge.topic.as_deref()))
+            .collect()
+    }
+
+    #[test]
+    fn node_overlay_lists_connections_mesh_fanout_and_direct_peers() {
+        let node = local_node();
+
+        let snapshot = node.overlay(OverlayOptions::default());
+
+        let ids: Vec<&str> = snapshot.nodes.iter().map(|node| node.id.as_str()).collect();
+        assert_eq!(ids, vec!["a", "b", "local", "relay"]);
+        assert_eq!(snapshot.nodes[1].topics, vec!["topic"]);
+        assert_eq!(
+            edges(&snapshot),
+            vec![
+                ("a", EdgeKind::Connection, None),
+                ("a", EdgeKind::Mesh, Some("topic")),
+                ("a", EdgeKind::Fanout, Some("other")),
+                ("b", EdgeKind::Connection, None),
+                ("b", EdgeKind::Mesh, Some("topic")),
+                ("relay", EdgeKind::Direct, None),
+            ]
+        );
+        assert_eq!(snapshot.edges[3].direction, Some(ConnectionDirection::Inbound));
+        assert!(snapshot.edges.iter().all(|edge| edge.score.is_none()));
+    }
+
+    #[test]
+    fn dot_and_json_exports() {
+        let node = local_node();
+
+        let dot = node.export_dot(OverlayOptions {
+            include_scores: true,
+        });
+        assert!(dot.starts_with("digraph gossipsub {\n"));
+        assert!(dot.contains("  \"b\" [label=\"b\\n[topic]\"];\n"));
+        assert!(dot.contains("  \"local\" -> \"a\" [color=blue, label=\"topic (0.00)\"];\n"));
+        assert!(
+            dot.contains("  \"local\" -> \"relay\" [color=red, style=bold, label=\"(0.00)\"];\n")
+        );
+
+        let json = node.export_json(OverlayOptions::default()).unwrap();
+        let json: Value = serde_json::from_str(&json).unwrap();
+        let fanout = &json["edges"][2];
+        assert_eq!(fanout["kind"], "fanout");
+        assert_eq!(fanout["topic"], "other");
+        assert!(fanout.get("score").is_none() && fanout.get("direction").is_none());
+    }
+
+    #[test]
+    fn dot_escapes_backslashes_and_quotes() {
+        let snapshot = OverlaySnapshot {
+            nodes: vec![OverlayNode {
+                id: "a\\\"b".to_string(),
+                topics: vec!["t\\n".to_string()],
+            }],
+            edges: Vec::new(),
+        };
+
+        let dot = snapshot.to_dot();
+
+        // 节点名中的反斜杠和引号都被转义，只有拼接的\n是DOT换行
+        assert!(dot.contains("  \"a\\\\\\\"b\" [label=\"a\\\\\\\"b\\n[t\\\\n]\"];\n"));
+    }
+
+    #[test]
+    fn simulator_overlay_keeps_one_edge_per_connection() {
+        let mut simulator = Simulator::new(SimulatorConfig::default());
+        for node_id in ["a", "b", "c"] {
+            simulator.add_node(node_id, GossipSubConfig::default());
+        }
+        simulator.connect("a", "b");
+        simulator.connect("b", "c");
+        for node_id in ["a", "b", "c"] {
+            simulator.subscribe(node_id, "topic");
+        }
+        simulator.run_for(3_000);
+
+        let snapshot = simulator.overlay(OverlayOptions::default());
+
+        assert!(snapshot.nodes.iter().all(|node| node.topics == vec!["topic"]));
+        let connections: Vec<(&str, &str)> = snapshot
+            .edges
+            .iter()
+            .filter(|edge| edge.kind == EdgeKind::Connection)
+            .map(|edge| (edge.from.as_str(), edge.to.as_str()))
+            .collect();
+        assert_eq!(connections, vec![("a", "b"), ("b", "c")]);
+        let mesh = snapshot
+            .edges
+            .iter()
+            .filter(|edge| edge.kind == EdgeKind::Mesh)
+            .count();
+        assert_eq!(mesh, 4);
+    }
+}
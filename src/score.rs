@@ -0,0 +1,76 @@
+use serde::{Deserialize, Serialize};
+
+// 节点评分参数
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PeerScoreParams {
+    pub behaviour_penalty_weight: f64,    // 行为惩罚权重(负数)
+    pub behaviour_penalty_threshold: f64, // 超过该值的惩罚才计入评分
+    pub behaviour_penalty_decay: f64,     // 每次心跳的惩罚衰减系数
+    pub decay_to_zero: f64,               // 低于该值时直接归零
+}
+
+impl Default for PeerScoreParams {
+    fn default() -> Self {
+        Self {
+            behaviour_penalty_weight: -10.0,
+            behaviour_penalty_threshold: 0.0,
+            behaviour_penalty_decay: 0.9,
+            decay_to_zero: 0.01,
+        }
+    }
+}
+
+// 评分阈值
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PeerScoreThresholds {
+    pub gossip_threshold: f64,    // 低于该评分的节点不参与gossip
+    pub publish_threshold: f64,   // 低于该评分的节点不会被选为fanout发布对象
+    pub graylist_threshold: f64,  // 低于该评分的节点发来的消息全部忽略
+    pub accept_px_threshold: f64, // 接受PRUNE中节点交换信息所需的最低评分
+    pub opportunistic_graft_threshold: f64, // mesh评分中位数低于该值时触发机会性GRAFT
+}
+
+impl Default for PeerScoreThresholds {
+    fn default() -> Self {
+        Self {
+            gossip_threshold: -10.0,
+            publish_threshold: -50.0,
+            graylist_threshold: -80.0,
+            accept_px_threshold: 0.0,
+            opportunistic_graft_threshold: 0.0,
+        }
+    }
+}
+
+// 单个peer的评分状态
+#[derive(Debug, Clone, Default)]
+pub struct PeerScore {
+    pub behaviour_penalty: f64, // 累计的行为惩罚计数
+}
+
+impl PeerScore {
+    // 计算当前评分
+    pub fn score(&self, params: &PeerScoreParams) -> f64 {
+        let excess = self.behaviour_penalty - params.behaviour_penalty_threshold;
+        if excess > 0.0 {
+            excess * excess * params.behaviour_penalty_weight
+        } else {
+            0.0
+        }
+    }
+
+    // 增加行为惩罚
+    pub fn add_penalty(&mut self, count: f64) {
+        self.behaviour_penalty += count;
+    }
+
+    // 心跳时衰减惩罚计数
+    pub fn decay(&mut self, params: &PeerScoreParams) {
+        self.behaviour_penalty *= params.behaviour_penalty_decay;
+        if self.behaviour_penalty < params.decay_to_zero {
+            self.behaviour_penalty = 0.0;
+        }
+    }
+}
@@ -0,0 +1,418 @@
+use crate::clock::ManualClock;
+use crate::error::GossipSubError;
+use crate::message::GossipMessage;
+use crate::node::GossipSubNode;
+use crate::types::{GossipSubConfig, MessageType};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tracing::trace;
+
+// 虚拟网络的链路条件
+#[derive(Debug, Clone)]
+pub struct NetworkConditions {
+    pub latency: u64,           // 基础延迟(ms)
+    pub jitter: u64,            // 在基础延迟上随机增加的最大抖动(ms)
+    pub loss_rate: f64,         // 丢包率 0.0 - 1.0
+    pub bandwidth: Option<u64>, // 每条链路的带宽(字节/ms)，None表示不限
+}
+
+impl Default for NetworkConditions {
+    fn default() -> Self {
+        Self {
+            latency: 50,
+            jitter: 10,
+            loss_rate: 0.0,
+            bandwidth: None,
+        }
+    }
+}
+
+// 模拟器配置
+#[derive(Debug, Clone, Default)]
+pub struct SimulatorConfig {
+    pub seed: u64, // 决定网络条件和各节点随机数种子
+    pub network: NetworkConditions,
+}
+
+// 网络中的一条消息
+#[derive(Debug, Clone)]
+struct Envelope {
+    from: String,
+    to: String,
+    message: GossipMessage,
+}
+
+// 模拟网络的统计
+#[derive(Debug, Clone, Default)]
+pub struct SimulationStats {
+    pub sent: u64,      // 进入网络的消息数
+    pub delivered: u64, // 成功投递的消息数
+    pub dropped: u64,   // 因丢包或目标断开而丢弃的消息数
+    pub bytes: u64,     // 进入网络的总字节数
+}
+
+// 发布消息的送达情况
+#[derive(Debug, Clone)]
+pub struct DeliveryReport {
+    pub message_id: String,
+    pub topic: String,
+    pub published_at: u64,
+    pub delivered_to: BTreeMap<String, u64>, // 节点 -> 首次收到的时间
+}
+
+impl DeliveryReport {
+    // 最慢送达节点的延迟(ms)
+    pub fn max_latency(&self) -> Option<u64> {
+        self.delivered_to
+            .values()
+            .map(|&at| at - self.published_at)
+            .max()
+    }
+}
+
+// 确定性的内存网络模拟器
+pub struct Simulator {
+    pub config: SimulatorConfig,
+    pub stats: SimulationStats,
+    nodes: BTreeMap<String, GossipSubNode>,
+    outbound: BTreeMap<String, UnboundedReceiver<(String, GossipMessage)>>, // 节点 -> 待发送消息
+    next_heartbeat: BTreeMap<String, u64>,   // 节点 -> 下次心跳的模拟时间
+    in_flight: BTreeMap<(u64, u64), Envelope>, // (送达时间, 序号) -> 消息
+    link_busy_until: HashMap<(String, String), u64>, // 链路 -> 发送队列空闲的时间
+    deliveries: HashMap<String, DeliveryReport>, // messageId -> 送达情况
+    now: u64,
+    clock: ManualClock, // 所有节点共享的时钟，跟随模拟时间
+    next_seq: u64,
+    rng: StdRng,
+}
+
+impl Simulator {
+    pub fn new(config: SimulatorConfig) -> Self {
+        let rng = StdRng::seed_from_u64(config.seed);
+        Self {
+            config,
+            stats: SimulationStats::default(),
+            nodes: BTreeMap::new(),
+            outbound: BTreeMap::new(),
+            next_heartbeat: BTreeMap::new(),
+            in_flight: BTreeMap::new(),
+            link_busy_until: HashMap::new(),
+            deliveries: HashMap::new(),
+            now: 0,
+            clock: ManualClock::new(0),
+            next_seq: 0,
+            rng,
+        }
+    }
+
+    // 当前模拟时间(ms)
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    // 添加节点，未设置种子时从模拟器种子派生
+    pub fn add_node(&mut self, node_id: &str, mut config: GossipSubConfig) {
+        if config.rng_seed.is_none() {
+            config.rng_seed = Some(self.rng.random());
+        }
+        let first_heartbeat = self.now + config.heartbeat_interval;
+        let mut node = GossipSubNode::with_clock(
+            node_id.to_string(),
+            config,
+            Arc::new(self.clock.clone()),
+        );
+        self.outbound
+            .insert(node_id.to_string(), node.outbound_stream());
+        self.nodes.insert(node_id.to_string(), node);
+        self.next_heartbeat
+            .insert(node_id.to_string(), first_heartbeat);
+        self.flush_outbound(node_id);
+    }
+
+    // 建立从dialer到listener的连接
+    pub fn connect(&mut self, dialer: &str, listener: &str) {
+        if let Some(node) = self.nodes.get_mut(dialer) {
+            node.add_peer(listener.to_string(), format!("sim://{}", listener));
+        }
+        if let Some(node) = self.nodes.get_mut(listener) {
+            node.add_inbound_peer(dialer.to_string(), format!("sim://{}", dialer));
+        }
+        self.flush_outbound(dialer);
+        self.flush_outbound(listener);
+    }
+
+    // 断开两个节点之间的连接
+    pub fn disconnect(&mut self, a: &str, b: &str) {
+        if let Some(node) = self.nodes.get_mut(a) {
+            node.remove_peer(b);
+        }
+        if let Some(node) = self.nodes.get_mut(b) {
+            node.remove_peer(a);
+        }
+    }
+
+    pub fn subscribe(&mut self, node_id: &str, topic: &str) {
+        if let Some(node) = self.nodes.get_mut(node_id) {
+            node.subscribe(topic.to_string());
+        }
+        self.flush_outbound(node_id);
+    }
+
+    pub fn publish(
+        &mut self,
+        node_id: &str,
+        topic: &str,
+        content: Vec<u8>,
+    ) -> Result<String, GossipSubError> {
+        let node = self
+            .nodes
+            .get_mut(node_id)
+            .ok_or_else(|| GossipSubError::Transport(format!("节点 {} 不存在", node_id)))?;
+        let message_id = node.publish(topic, content)?;
+
+        self.deliveries.insert(
+            message_id.clone(),
+            DeliveryReport {
+                message_id: message_id.clone(),
+                topic: topic.to_string(),
+                published_at: self.now,
+                delivered_to: BTreeMap::new(),
+            },
+        );
+        self.flush_outbound(node_id);
+        Ok(message_id)
+    }
+
+    pub fn node(&self, node_id: &str) -> Option<&GossipSubNode> {
+        self.nodes.get(node_id)
+    }
+
+    pub fn node_mut(&mut self, node_id: &str) -> Option<&mut GossipSubNode> {
+        self.nodes.get_mut(node_id)
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &GossipSubNode> {
+        self.nodes.values()
+    }
+
+    pub fn delivery_report(&self, message_id: &str) -> Option<&DeliveryReport> {
+        self.deliveries.get(message_id)
+    }
+
+    // 推进模拟时间，按时间顺序处理消息投递和心跳
+    pub fn run_for(&mut self, duration: u64) {
+        let end = self.now + duration;
+
+        loop {
+            let next_delivery = self.in_flight.keys().next().map(|&(at, _)| at);
+            let next_heartbeat = self
+                .next_heartbeat
+                .iter()
+                .min_by_key(|&(_, &at)| at)
+                .map(|(node_id, &at)| (node_id.clone(), at));
+
+            match (next_delivery, next_heartbeat) {
+                (Some(delivery_at), Some((_, heartbeat_at)))
+                    if delivery_at <= heartbeat_at && delivery_at <= end =>
+                {
+                    self.advance_to(delivery_at);
+                    self.deliver_next();
+                }
+                (Some(delivery_at), None) if delivery_at <= end => {
+                    self.advance_to(delivery_at);
+                    self.deliver_next();
+                }
+                (_, Some((node_id, heartbeat_at))) if heartbeat_at <= end => {
+                    self.advance_to(heartbeat_at);
+                    self.heartbeat(&node_id);
+                }
+                _ => break,
+            }
+        }
+
+        self.advance_to(end);
+    }
+
+    fn advance_to(&mut self, time: u64) {
+        self.now = time;
+        self.clock.set(time);
+    }
+
+    fn deliver_next(&mut self) {
+        let Some((_, envelope)) = self.in_flight.pop_first() else {
+            return;
+        };
+
+        let Some(node) = self.nodes.get_mut(&envelope.to) else {
+            self.stats.dropped += 1;
+            return;
+        };
+        // 连接已断开的消息直接丢弃
+        if !node.peers.contains_key(&envelope.from) {
+            self.stats.dropped += 1;
+            return;
+        }
+
+        self.stats.delivered += 1;
+        let message_id = envelope.message.message_id.clone();
+        let is_publish = envelope.message.message_type == MessageType::Publish;
+        let accepted = node.handle_message(envelope.message, &envelope.from).is_ok();
+
+        // 只统计订阅了该主题的节点
+        if is_publish
+            && accepted
+            && let Some(report) = self.deliveries.get_mut(&message_id)
+            && node.topics.contains(&report.topic)
+        {
+            report
+                .delivered_to
+                .entry(envelope.to.clone())
+                .or_insert(self.now);
+        }
+
+        self.flush_outbound(&envelope.to);
+    }
+
+    fn heartbeat(&mut self, node_id: &str) {
+        if let Some(node) = self.nodes.get_mut(node_id) {
+            let _ = node.gossip_heartbeat();
+            let interval = node.config.heartbeat_interval.max(1);
+            self.next_heartbeat
+                .insert(node_id.to_string(), self.now + interval);
+        }
+        self.flush_outbound(node_id);
+    }
+
+    // 取出节点待发送的消息并放入虚拟网络
+    fn flush_outbound(&mut self, node_id: &str) {
+        let Some(receiver) = self.outbound.get_mut(node_id) else {
+            return;
+        };
+        let mut outbound: Vec<(String, GossipMessage)> =
+            std::iter::from_fn(|| receiver.try_recv().ok()).collect();
+        // 按目标排序（稳定排序保留同一目标的发送顺序），消除HashMap迭代顺序的影响
+        outbound.sort_by(|a, b| a.0.cmp(&b.0));
+
+        for (to, message) in outbound {
+            self.schedule(node_id, to, message);
+        }
+    }
+
+    fn schedule(&mut self, from: &str, to: String, message: GossipMessage) {
+        let network = &self.config.network;
+        let size = message.encoded_len() as u64;
+        self.stats.sent += 1;
+        self.stats.bytes += size;
+
+        if network.loss_rate > 0.0 && self.rng.random::<f64>() < network.loss_rate {
+            trace!(from, to = %to, message_id = %message.message_id, "模拟丢包");
+            self.stats.dropped += 1;
+            return;
+        }
+
+        // 带宽受限时，同一链路上的消息依次发送
+        let mut departure = self.now;
+        if let Some(bandwidth) = network.bandwidth.filter(|&b| b > 0) {
+            let busy_until = self
+                .link_busy_until
+                .entry((from.to_string(), to.clone()))
+                .or_insert(0);
+            departure = departure.max(*busy_until) + size.div_ceil(bandwidth);
+            *busy_until = departure;
+        }
+
+        let jitter = if network.jitter > 0 {
+            self.rng.random_range(0..=network.jitter)
+        } else {
+            0
+        };
+        let deliver_at = departure + network.latency + jitter;
+
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.in_flight.insert(
+            (deliver_at, seq),
+            Envelope {
+                from: from.to_string(),
+                to,
+                message,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOPIC: &str = "topic";
+
+    // 全连接的5节点网络，订阅后发布一条消息，返回消息ID、送达情况和各节点的mesh
+    fn run(seed: u64) -> (String, BTreeMap<String, u64>, Vec<Vec<String>>) {
+        let mut sim = Simulator::new(SimulatorConfig {
+            seed,
+            network: NetworkConditions {
+                loss_rate: 0.1,
+                ..NetworkConditions::default()
+            },
+        });
+        let ids: Vec<String> = (0..5).map(|i| format!("node{}", i)).collect();
+        for id in &ids {
+            sim.add_node(id, GossipSubConfig::default());
+        }
+        for (i, a) in ids.iter().enumerate() {
+            for b in &ids[i + 1..] {
+                sim.connect(a, b);
+            }
+        }
+        for id in &ids {
+            sim.subscribe(id, TOPIC);
+        }
+        sim.run_for(3000);
+        let message_id = sim.publish("node0", TOPIC, b"hello".to_vec()).unwrap();
+        sim.run_for(3000);
+
+        let delivered = sim.delivery_report(&message_id).unwrap().delivered_to.clone();
+        let meshes = sim
+            .nodes()
+            .map(|node| {
+                let mut peers: Vec<String> = node.mesh[TOPIC].iter().cloned().collect();
+                peers.sort();
+                peers
+            })
+            .collect();
+        (message_id, delivered, meshes)
+    }
+
+    #[test]
+    fn same_seed_reproduces_the_run() {
+        assert_eq!(run(7), run(7));
+    }
+
+    #[test]
+    fn message_ids_follow_the_seed() {
+        assert_ne!(run(7).0, run(8).0);
+    }
+
+    #[test]
+    fn nodes_with_the_same_seed_publish_distinct_ids() {
+        let mut sim = Simulator::new(SimulatorConfig::default());
+        let config = GossipSubConfig {
+            rng_seed: Some(1),
+            ..GossipSubConfig::default()
+        };
+        sim.add_node("a", config.clone());
+        sim.add_node("b", config);
+        sim.connect("a", "b");
+        sim.subscribe("a", TOPIC);
+        sim.subscribe("b", TOPIC);
+
+        let from_a = sim.publish("a", TOPIC, b"hello".to_vec()).unwrap();
+        let from_b = sim.publish("b", TOPIC, b"hello".to_vec()).unwrap();
+
+        assert_ne!(from_a, from_b);
+    }
+}
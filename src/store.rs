@@ -0,0 +1,669 @@
+use crate::error::GossipSubError;
+use crate::message::GossipMessage;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use tracing::{info, warn};
+
+// 消息保留策略，None表示不限制
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionPolicy {
+    pub max_age: Option<u64>,        // 按消息时间戳计算的最长保留时间(ms)
+    pub max_messages: Option<usize>, // 最多保留的消息数
+    pub max_bytes: Option<u64>,      // 最多保留的消息总字节数
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            max_age: Some(24 * 60 * 60 * 1000), // 1天
+            max_messages: Some(10000),
+            max_bytes: None,
+        }
+    }
+}
+
+// 持久化的主题消息历史
+pub trait MessageStore: Send {
+    // 追加一条已接受的消息，messageId已存在时忽略并返回false
+    fn append(&mut self, message: &GossipMessage) -> Result<bool, GossipSubError>;
+
+    fn get(&self, message_id: &str) -> Option<GossipMessage>;
+
+    // 主题在 [from, to] 时间范围内的消息，按时间升序，最多limit条
+    fn range(&self, topic: &str, from: u64, to: u64, limit: usize) -> Vec<GossipMessage>;
+
+    // 主题中排在指定消息之后的消息，按时间升序，最多limit条
+    fn since(&self, topic: &str, message_id: &str, limit: usize) -> Vec<GossipMessage>;
+
+    // 主题中时间戳不晚于to的最近limit条消息，按时间升序
+    fn latest(&self, topic: &str, to: u64, limit: usize) -> Vec<GossipMessage>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // 按保留策略删除旧消息，返回删除的消息数
+    fn apply_retention(&mut self, now: u64) -> Result<usize, GossipSubError>;
+}
+
+// 消息存储配置
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StoreConfig {
+    pub path: Option<PathBuf>, // 日志文件路径，None时只保存在内存中
+    pub retention: RetentionPolicy,
+}
+
+impl StoreConfig {
+    pub fn open(&self) -> Result<Box<dyn MessageStore>, GossipSubError> {
+        match &self.path {
+            Some(path) => Ok(Box::new(FileStore::open(path, self.retention.clone())?)),
+            None => Ok(Box::new(MemoryStore::new(self.retention.clone()))),
+        }
+    }
+}
+
+// 索引中的一条消息
+#[derive(Debug, Clone)]
+struct StoredEntry {
+    message: GossipMessage,
+    key: (u64, u64), // (时间戳, 序号)，同一时间戳按写入顺序排列
+    size: u64,
+}
+
+// 按messageId和按时间的索引，两种实现共用
+#[derive(Debug, Default)]
+struct StoreIndex {
+    entries: HashMap<String, StoredEntry>, // messageId -> 消息
+    by_time: HashMap<String, BTreeMap<(u64, u64), String>>, // topic -> (时间戳, 序号) -> messageId
+    next_seq: u64,
+    total_bytes: u64,
+}
+
+impl StoreIndex {
+    fn insert(&mut self, message: &GossipMessage) -> bool {
+        let Some(topic) = &message.topic else {
+            return false;
+        };
+        if self.entries.contains_key(&message.message_id) {
+            return false;
+        }
+
+        let key = (message.timestamp, self.next_seq);
+        self.next_seq += 1;
+        let size = message.encoded_len() as u64;
+        self.total_bytes += size;
+        self.by_time
+            .entry(topic.clone())
+            .or_default()
+            .insert(key, message.message_id.clone());
+        self.entries.insert(
+            message.message_id.clone(),
+            StoredEntry {
+                message: message.clone(),
+                key,
+                size,
+            },
+        );
+        true
+    }
+
+    fn remove(&mut self, message_id: &str) {
+        let Some(entry) = self.entries.remove(message_id) else {
+            return;
+        };
+        self.total_bytes -= entry.size;
+        if let Some(topic) = &entry.message.topic
+            && let Some(times) = self.by_time.get_mut(topic)
+        {
+            times.remove(&entry.key);
+            if times.is_empty() {
+                self.by_time.remove(topic);
+            }
+        }
+    }
+
+    fn range(&self, topic: &str, from: u64, to: u64, limit: usize) -> Vec<GossipMessage> {
+        let Some(times) = self.by_time.get(topic) else {
+            return Vec::new();
+        };
+        times
+            .range((from, 0)..=(to, u64::MAX))
+            .take(limit)
+            .filter_map(|(_, message_id)| self.entries.get(message_id))
+            .map(|entry| entry.message.clone())
+            .collect()
+    }
+
+    fn since(&self, topic: &str, message_id: &str, limit: usize) -> Vec<GossipMessage> {
+        let (Some(times), Some(entry)) = (self.by_time.get(topic), self.entries.get(message_id))
+        else {
+            return Vec::new();
+        };
+        times
+            .range((entry.key.0, entry.key.1 + 1)..)
+            .take(limit)
+            .filter_map(|(_, message_id)| self.entries.get(message_id))
+            .map(|entry| entry.message.clone())
+            .collect()
+    }
+
+    fn latest(&self, topic: &str, to: u64, limit: usize) -> Vec<GossipMessage> {
+        let Some(times) = self.by_time.get(topic) else {
+            return Vec::new();
+        };
+        let mut messages: Vec<GossipMessage> = times
+            .range(..=(to, u64::MAX))
+            .map(|(_, message_id)| message_id)
+            .rev()
+            .take(limit)
+            .filter_map(|message_id| self.entries.get(message_id))
+            .map(|entry| entry.message.clone())
+            .collect();
+        messages.reverse();
+        messages
+    }
+
+    // 按写入顺序排列的所有消息
+    fn ordered(&self) -> Vec<&StoredEntry> {
+        let mut entries: Vec<&StoredEntry> = self.entries.values().collect();
+        entries.sort_by_key(|entry| entry.key.1);
+        entries
+    }
+
+    // 找出超出保留策略的消息，从最旧的开始删除
+    fn expired(&self, retention: &RetentionPolicy, now: u64) -> Vec<String> {
+        let mut entries: Vec<&StoredEntry> = self.entries.values().collect();
+        entries.sort_by_key(|entry| entry.key);
+
+        let mut count = entries.len();
+        let mut bytes = self.total_bytes;
+        let mut expired = Vec::new();
+        for entry in entries {
+            let too_old = retention
+                .max_age
+                .is_some_and(|max_age| now.saturating_sub(entry.key.0) > max_age);
+            let too_many = retention.max_messages.is_some_and(|max| count > max);
+            let too_large = retention.max_bytes.is_some_and(|max| bytes > max);
+            if !(too_old || too_many || too_large) {
+                break;
+            }
+            count -= 1;
+            bytes -= entry.size;
+            expired.push(entry.message.message_id.clone());
+        }
+        expired
+    }
+}
+
+// 只保存在内存中的消息存储
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    index: StoreIndex,
+    retention: RetentionPolicy,
+}
+
+impl MemoryStore {
+    pub fn new(retention: RetentionPolicy) -> Self {
+        Self {
+            index: StoreIndex::default(),
+            retention,
+        }
+    }
+}
+
+impl MessageStore for MemoryStore {
+    fn append(&mut self, message: &GossipMessage) -> Result<bool, GossipSubError> {
+        Ok(self.index.insert(message))
+    }
+
+    fn get(&self, message_id: &str) -> Option<GossipMessage> {
+        self.index
+            .entries
+            .get(message_id)
+            .map(|entry| entry.message.clone())
+    }
+
+    fn range(&self, topic: &str, from: u64, to: u64, limit: usize) -> Vec<GossipMessage> {
+        self.index.range(topic, from, to, limit)
+    }
+
+    fn since(&self, topic: &str, message_id: &str, limit: usize) -> Vec<GossipMessage> {
+        self.index.since(topic, message_id, limit)
+    }
+
+    fn latest(&self, topic: &str, to: u64, limit: usize) -> Vec<GossipMessage> {
+        self.index.latest(topic, to, limit)
+    }
+
+    fn len(&self) -> usize {
+        self.index.entries.len()
+    }
+
+    fn apply_retention(&mut self, now: u64) -> Result<usize, GossipSubError> {
+        let expired = self.index.expired(&self.retention, now);
+        for message_id in &expired {
+            self.index.remove(message_id);
+        }
+        Ok(expired.len())
+    }
+}
+
+// 日志中已删除的行超过该数量且超过存活消息数时才压缩
+const COMPACT_MIN_DEAD_LINES: usize = 1000;
+
+// 基于文件的消息存储：每行一条JSON消息的追加日志，启动时重放日志恢复索引
+// 保留策略删除的消息不写墓碑，日志压缩前重启会重新加载，随后的apply_retention会再次删除
+// 文件读写在后台线程中进行，append只更新索引并排队，不阻塞调用方
+pub struct FileStore {
+    path: PathBuf,
+    writer: Option<mpsc::Sender<WriterCommand>>, // 发往写线程的命令，Drop时关闭
+    writer_thread: Option<JoinHandle<()>>,
+    index: StoreIndex,
+    retention: RetentionPolicy,
+    dead_lines: usize, // 日志中已不在索引里的行数
+}
+
+impl FileStore {
+    pub fn open(path: impl AsRef<Path>, retention: RetentionPolicy) -> Result<Self, GossipSubError> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).map_err(|e| storage_error(&path, e))?;
+        }
+
+        // 重放日志；崩溃时可能留下写了一半的最后一行，跳过无法解析的行并在之后压缩
+        let mut index = StoreIndex::default();
+        let mut corrupt = 0;
+        let mut lines = 0;
+        if path.exists() {
+            let file = File::open(&path).map_err(|e| storage_error(&path, e))?;
+            for line in BufReader::new(file).lines() {
+                let line = line.map_err(|e| storage_error(&path, e))?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                lines += 1;
+                match serde_json::from_str::<GossipMessage>(&line) {
+                    Ok(message) => {
+                        index.insert(&message);
+                    }
+                    Err(e) => {
+                        warn!(path = %path.display(), error = %e, "跳过无法解析的日志行");
+                        corrupt += 1;
+                    }
+                }
+            }
+        }
+
+        let file = open_append(&path)?;
+        let (writer, commands) = mpsc::channel();
+        let log = LogWriter {
+            path: path.clone(),
+            file: BufWriter::new(file),
+            error: None,
+        };
+        let writer_thread = thread::Builder::new()
+            .name("message-store".to_string())
+            .spawn(move || log.run(commands))
+            .map_err(|e| storage_error(&path, e))?;
+
+        let dead_lines = lines - index.entries.len();
+        let mut store = Self {
+            path,
+            writer: Some(writer),
+            writer_thread: Some(writer_thread),
+            index,
+            retention,
+            dead_lines,
+        };
+        info!(
+            path = %store.path.display(),
+            messages = store.index.entries.len(),
+            "消息存储已恢复"
+        );
+        if corrupt > 0 {
+            store.compact()?;
+        }
+        // 保留策略不随日志保存，重启后先删除已过期的消息
+        store.apply_retention(GossipMessage::current_timestamp())?;
+        Ok(store)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // 用当前索引重写日志：写线程先写临时文件再替换，避免中途崩溃丢失数据
+    pub fn compact(&mut self) -> Result<(), GossipSubError> {
+        let mut content = Vec::new();
+        for entry in self.index.ordered() {
+            content.extend(encode_line(&entry.message)?);
+        }
+        self.send(WriterCommand::Rewrite(content))?;
+        self.dead_lines = 0;
+        Ok(())
+    }
+
+    // 等待已排队的写入完成，返回此前写线程遇到的错误
+    pub fn flush(&self) -> Result<(), GossipSubError> {
+        let (ack, done) = mpsc::channel();
+        self.send(WriterCommand::Flush(ack))?;
+        done.recv().map_err(|_| writer_stopped(&self.path))?
+    }
+
+    fn send(&self, command: WriterCommand) -> Result<(), GossipSubError> {
+        self.writer
+            .as_ref()
+            .and_then(|writer| writer.send(command).ok())
+            .ok_or_else(|| writer_stopped(&self.path))
+    }
+}
+
+impl Drop for FileStore {
+    // 关闭命令通道，等待写线程写完剩余的消息
+    fn drop(&mut self) {
+        self.writer.take();
+        if let Some(writer_thread) = self.writer_thread.take() {
+            let _ = writer_thread.join();
+        }
+    }
+}
+
+// 写线程的命令
+enum WriterCommand {
+    Append(Vec<u8>),
+    Rewrite(Vec<u8>), // 压缩后的完整日志内容
+    Flush(mpsc::Sender<Result<(), GossipSubError>>),
+}
+
+// 后台写线程：批量处理已排队的命令后再刷盘
+struct LogWriter {
+    path: PathBuf,
+    file: BufWriter<File>,
+    error: Option<GossipSubError>, // 下次Flush时报告的第一个写入错误
+}
+
+impl LogWriter {
+    fn run(mut self, commands: mpsc::Receiver<WriterCommand>) {
+        while let Ok(command) = commands.recv() {
+            self.handle(command);
+            while let Ok(command) = commands.try_recv() {
+                self.handle(command);
+            }
+            self.flush_buffer();
+        }
+        self.flush_buffer();
+    }
+
+    fn handle(&mut self, command: WriterCommand) {
+        match command {
+            WriterCommand::Append(line) => {
+                let result = self.file.write_all(&line).map_err(|e| storage_error(&self.path, e));
+                self.record(result);
+            }
+            WriterCommand::Rewrite(content) => {
+                let result = self.rewrite(&content);
+                self.record(result);
+            }
+            WriterCommand::Flush(ack) => {
+                self.flush_buffer();
+                let _ = ack.send(self.error.take().map_or(Ok(()), Err));
+            }
+        }
+    }
+
+    fn rewrite(&mut self, content: &[u8]) -> Result<(), GossipSubError> {
+        self.file.flush().map_err(|e| storage_error(&self.path, e))?;
+        let tmp_path = self.path.with_extension("compact");
+        let mut tmp = File::create(&tmp_path).map_err(|e| storage_error(&tmp_path, e))?;
+        tmp.write_all(content).map_err(|e| storage_error(&tmp_path, e))?;
+        tmp.sync_all().map_err(|e| storage_error(&tmp_path, e))?;
+        fs::rename(&tmp_path, &self.path).map_err(|e| storage_error(&self.path, e))?;
+        self.file = BufWriter::new(open_append(&self.path)?);
+        Ok(())
+    }
+
+    fn flush_buffer(&mut self) {
+        let result = self.file.flush().map_err(|e| storage_error(&self.path, e));
+        self.record(result);
+    }
+
+    fn record(&mut self, result: Result<(), GossipSubError>) {
+        if let Err(e) = result {
+            warn!(path = %self.path.display(), error = %e, "写入消息日志失败");
+            self.error.get_or_insert(e);
+        }
+    }
+}
+
+impl MessageStore for FileStore {
+    fn append(&mut self, message: &GossipMessage) -> Result<bool, GossipSubError> {
+        if message.topic.is_none() || self.index.entries.contains_key(&message.message_id) {
+            return Ok(false);
+        }
+        // 日志由写线程写入，写入错误在flush时报告
+        self.send(WriterCommand::Append(encode_line(message)?))?;
+        Ok(self.index.insert(message))
+    }
+
+    fn get(&self, message_id: &str) -> Option<GossipMessage> {
+        self.index
+            .entries
+            .get(message_id)
+            .map(|entry| entry.message.clone())
+    }
+
+    fn range(&self, topic: &str, from: u64, to: u64, limit: usize) -> Vec<GossipMessage> {
+        self.index.range(topic, from, to, limit)
+    }
+
+    fn since(&self, topic: &str, message_id: &str, limit: usize) -> Vec<GossipMessage> {
+        self.index.since(topic, message_id, limit)
+    }
+
+    fn latest(&self, topic: &str, to: u64, limit: usize) -> Vec<GossipMessage> {
+        self.index.latest(topic, to, limit)
+    }
+
+    fn len(&self) -> usize {
+        self.index.entries.len()
+    }
+
+    fn apply_retention(&mut self, now: u64) -> Result<usize, GossipSubError> {
+        let expired = self.index.expired(&self.retention, now);
+        if expired.is_empty() {
+            return Ok(0);
+        }
+        for message_id in &expired {
+            self.index.remove(message_id);
+        }
+        self.dead_lines += expired.len();
+        if self.dead_lines >= self.index.entries.len().max(COMPACT_MIN_DEAD_LINES) {
+            self.compact()?;
+        }
+        Ok(expired.len())
+    }
+}
+
+fn open_append(path: &Path) -> Result<File, GossipSubError> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| storage_error(path, e))
+}
+
+// 每条消息编码为一整行，进程崩溃时最多丢失最后一行
+fn encode_line(message: &GossipMessage) -> Result<Vec<u8>, GossipSubError> {
+    let mut line = serde_json::to_vec(message).map_err(|e| GossipSubError::Codec(e.to_string()))?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+fn storage_error(path: &Path, error: std::io::Error) -> GossipSubError {
+    GossipSubError::Storage(format!("{}: {}", path.display(), error))
+}
+
+fn writer_stopped(path: &Path) -> GossipSubError {
+    GossipSubError::Storage(format!("{}: 写线程已停止", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::MessageType;
+
+    const TOPIC: &str = "topic";
+
+    fn message(id: &str, timestamp: u64) -> GossipMessage {
+        GossipMessage::new(MessageType::Publish)
+            .with_message_id(id.to_string())
+            .with_topic(TOPIC.to_string())
+            .with_content(id.as_bytes().to_vec())
+            .with_timestamp(timestamp)
+    }
+
+    fn ids(messages: &[GossipMessage]) -> Vec<&str> {
+        messages.iter().map(|m| m.message_id.as_str()).collect()
+    }
+
+    fn unlimited() -> RetentionPolicy {
+        RetentionPolicy {
+            max_age: None,
+            max_messages: None,
+            max_bytes: None,
+        }
+    }
+
+    // 每个测试使用独立的日志文件
+    fn log_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir()
+            .join(format!("gossipsub-store-{}-{}", std::process::id(), name))
+            .join("messages.log");
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn log_lines(path: &Path) -> usize {
+        fs::read_to_string(path).unwrap().lines().count()
+    }
+
+    #[test]
+    fn memory_store_queries() {
+        let mut store = MemoryStore::new(unlimited());
+        for (id, timestamp) in [("a", 10), ("b", 20), ("c", 30), ("d", 40)] {
+            assert!(store.append(&message(id, timestamp)).unwrap());
+        }
+        assert!(!store.append(&message("a", 10)).unwrap());
+
+        assert_eq!(ids(&store.range(TOPIC, 20, 30, 10)), vec!["b", "c"]);
+        assert_eq!(ids(&store.since(TOPIC, "b", 1)), vec!["c"]);
+        assert_eq!(ids(&store.latest(TOPIC, 30, 2)), vec!["b", "c"]);
+        assert!(store.range("other", 0, u64::MAX, 10).is_empty());
+    }
+
+    #[test]
+    fn retention_drops_oldest_first() {
+        let mut store = MemoryStore::new(RetentionPolicy {
+            max_age: Some(100),
+            max_messages: Some(2),
+            max_bytes: None,
+        });
+        for (id, timestamp) in [("a", 10), ("b", 900), ("c", 950), ("d", 1000)] {
+            store.append(&message(id, timestamp)).unwrap();
+        }
+
+        assert_eq!(store.apply_retention(1000).unwrap(), 2);
+        assert_eq!(ids(&store.latest(TOPIC, u64::MAX, 10)), vec!["c", "d"]);
+        assert_eq!(store.apply_retention(1051).unwrap(), 1);
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn file_store_recovers_after_restart() {
+        let path = log_path("restart");
+        let now = GossipMessage::current_timestamp();
+        {
+            let mut store = FileStore::open(&path, RetentionPolicy::default()).unwrap();
+            store.append(&message("a", now)).unwrap();
+            store.append(&message("b", now + 1)).unwrap();
+            store.flush().unwrap();
+        }
+
+        let store = FileStore::open(&path, RetentionPolicy::default()).unwrap();
+        assert_eq!(ids(&store.latest(TOPIC, u64::MAX, 10)), vec!["a", "b"]);
+        assert_eq!(store.get("b").unwrap().content, Some(b"b".to_vec()));
+    }
+
+    #[test]
+    fn file_store_recovers_from_truncated_last_line() {
+        let path = log_path("truncated");
+        let now = GossipMessage::current_timestamp();
+        {
+            let mut store = FileStore::open(&path, unlimited()).unwrap();
+            store.append(&message("a", now)).unwrap();
+            store.append(&message("b", now)).unwrap();
+        }
+        // 模拟写到一半时崩溃
+        let mut file = open_append(&path).unwrap();
+        file.write_all(br#"{"message_type":"Publish","message_id":"c"#).unwrap();
+        drop(file);
+
+        let mut store = FileStore::open(&path, unlimited()).unwrap();
+        assert_eq!(store.len(), 2);
+        store.append(&message("d", now)).unwrap();
+        drop(store);
+
+        let store = FileStore::open(&path, unlimited()).unwrap();
+        assert_eq!(ids(&store.latest(TOPIC, u64::MAX, 10)), vec!["a", "b", "d"]);
+        assert_eq!(log_lines(&path), 3);
+    }
+
+    #[test]
+    fn file_store_applies_retention_on_open() {
+        let path = log_path("retention");
+        let now = GossipMessage::current_timestamp();
+        {
+            let mut store = FileStore::open(&path, unlimited()).unwrap();
+            for (i, id) in ["a", "b", "c"].into_iter().enumerate() {
+                store.append(&message(id, now + i as u64)).unwrap();
+            }
+        }
+
+        let retention = RetentionPolicy {
+            max_messages: Some(1),
+            ..unlimited()
+        };
+        let store = FileStore::open(&path, retention).unwrap();
+        assert_eq!(ids(&store.latest(TOPIC, u64::MAX, 10)), vec!["c"]);
+    }
+
+    #[test]
+    fn compact_rewrites_the_log() {
+        let path = log_path("compact");
+        let now = GossipMessage::current_timestamp();
+        let retention = RetentionPolicy {
+            max_messages: Some(1),
+            ..unlimited()
+        };
+        let mut store = FileStore::open(&path, retention).unwrap();
+        store.append(&message("a", now)).unwrap();
+        store.append(&message("b", now + 1)).unwrap();
+        assert_eq!(store.apply_retention(now).unwrap(), 1);
+        store.flush().unwrap();
+        assert_eq!(log_lines(&path), 2);
+
+        store.compact().unwrap();
+        store.flush().unwrap();
+        assert_eq!(log_lines(&path), 1);
+        assert_eq!(ids(&store.latest(TOPIC, u64::MAX, 10)), vec!["b"]);
+    }
+}
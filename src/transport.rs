@@ -0,0 +1,283 @@
+use crate::error::GossipSubError;
+use crate::message::GossipMessage;
+use crate::types::ConnectionDirection;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{self, Sender};
+use tracing::{debug, trace};
+
+// 单个帧的最大长度(字节)，防止恶意的长度前缀耗尽内存
+pub const MAX_FRAME_SIZE: usize = 4 * 1024 * 1024;
+// 握手超时
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+// 每个连接等待写出的消息上限，对方读得太慢时运行时丢弃新消息
+pub const OUTBOUND_QUEUE_SIZE: usize = 1024;
+// 连接任务上报给运行时的事件上限，队列满时读任务等待，不再继续读取
+pub const EVENT_QUEUE_SIZE: usize = 1024;
+
+// 连接上传输的帧
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Frame {
+    // 建立连接后双方首先交换身份和监听地址
+    Hello {
+        peer_id: String,
+        listen_address: String,
+    },
+    Message(Box<GossipMessage>),
+}
+
+// 写入一帧：4字节大端长度前缀 + JSON
+pub async fn write_frame<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    frame: &Frame,
+) -> Result<(), GossipSubError> {
+    let body = serde_json::to_vec(frame).map_err(|e| GossipSubError::Codec(e.to_string()))?;
+    if body.len() > MAX_FRAME_SIZE {
+        return Err(GossipSubError::Codec(format!(
+            "帧过大: {} 字节 (上限 {} 字节)",
+            body.len(),
+            MAX_FRAME_SIZE
+        )));
+    }
+
+    writer
+        .write_u32(body.len() as u32)
+        .await
+        .map_err(|e| GossipSubError::Transport(e.to_string()))?;
+    writer
+        .write_all(&body)
+        .await
+        .map_err(|e| GossipSubError::Transport(e.to_string()))?;
+    writer
+        .flush()
+        .await
+        .map_err(|e| GossipSubError::Transport(e.to_string()))
+}
+
+// 读取一帧，对方正常关闭连接时返回None
+pub async fn read_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> Result<Option<Frame>, GossipSubError> {
+    let len = match reader.read_u32().await {
+        Ok(len) => len as usize,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(GossipSubError::Transport(e.to_string())),
+    };
+    if len > MAX_FRAME_SIZE {
+        return Err(GossipSubError::Codec(format!(
+            "帧过大: {} 字节 (上限 {} 字节)",
+            len, MAX_FRAME_SIZE
+        )));
+    }
+
+    let mut body = vec![0; len];
+    reader
+        .read_exact(&mut body)
+        .await
+        .map_err(|e| GossipSubError::Transport(e.to_string()))?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| GossipSubError::Codec(e.to_string()))
+}
+
+// 连接任务上报给节点运行时的事件
+#[derive(Debug)]
+pub enum ConnectionEvent {
+    Established {
+        connection_id: u64,
+        peer_id: String,
+        listen_address: String, // 对方的监听地址，用于PX和重连
+        direction: ConnectionDirection,
+        sender: Sender<GossipMessage>, // 向该连接发送消息
+    },
+    Message {
+        peer_id: String,
+        message: Box<GossipMessage>,
+    },
+    Closed {
+        connection_id: u64,
+        peer_id: String,
+    },
+    DialFailed {
+        address: String,
+        error: GossipSubError,
+    },
+}
+
+// 本节点在握手中声明的身份
+#[derive(Debug, Clone)]
+pub struct LocalIdentity {
+    pub peer_id: String,
+    pub listen_address: String,
+}
+
+// 拨号并运行出站连接
+pub async fn dial(
+    address: String,
+    identity: LocalIdentity,
+    connection_id: u64,
+    events: Sender<ConnectionEvent>,
+) {
+    let stream = match TcpStream::connect(&address).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            let _ = events
+                .send(ConnectionEvent::DialFailed {
+                    address,
+                    error: GossipSubError::Transport(e.to_string()),
+                })
+                .await;
+            return;
+        }
+    };
+    if let Err(error) = run_connection(
+        stream,
+        identity,
+        ConnectionDirection::Outbound,
+        connection_id,
+        events.clone(),
+    )
+    .await
+    {
+        let _ = events
+            .send(ConnectionEvent::DialFailed { address, error })
+            .await;
+    }
+}
+
+// 握手并转发消息，直到连接关闭；握手失败时返回错误
+pub async fn run_connection(
+    stream: TcpStream,
+    identity: LocalIdentity,
+    direction: ConnectionDirection,
+    connection_id: u64,
+    events: Sender<ConnectionEvent>,
+) -> Result<(), GossipSubError> {
+    let _ = stream.set_nodelay(true);
+    let (mut reader, mut writer) = stream.into_split();
+
+    let hello = Frame::Hello {
+        peer_id: identity.peer_id.clone(),
+        listen_address: identity.listen_address,
+    };
+    let handshake = async {
+        write_frame(&mut writer, &hello).await?;
+        match read_frame(&mut reader).await? {
+            Some(Frame::Hello {
+                peer_id,
+                listen_address,
+            }) => Ok((peer_id, listen_address)),
+            _ => Err(GossipSubError::Codec("握手失败：未收到Hello".to_string())),
+        }
+    };
+    let (peer_id, listen_address) = tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake)
+        .await
+        .map_err(|_| GossipSubError::Transport("握手超时".to_string()))??;
+    if peer_id == identity.peer_id {
+        return Err(GossipSubError::Transport("不能连接到自己".to_string()));
+    }
+    debug!(peer = %peer_id, ?direction, "连接已建立");
+
+    let (sender, mut outgoing) = mpsc::channel::<GossipMessage>(OUTBOUND_QUEUE_SIZE);
+    let _ = events
+        .send(ConnectionEvent::Established {
+            connection_id,
+            peer_id: peer_id.clone(),
+            listen_address,
+            direction,
+            sender,
+        })
+        .await;
+
+    // 写任务：运行时丢弃发送端时结束并关闭写方向
+    tokio::spawn(async move {
+        while let Some(message) = outgoing.recv().await {
+            if let Err(e) = write_frame(&mut writer, &Frame::Message(Box::new(message))).await {
+                trace!(error = %e, "写入失败");
+                break;
+            }
+        }
+        let _ = writer.shutdown().await;
+    });
+
+    loop {
+        match read_frame(&mut reader).await {
+            Ok(Some(Frame::Message(message))) => {
+                if events
+                    .send(ConnectionEvent::Message {
+                        peer_id: peer_id.clone(),
+                        message,
+                    })
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+            Ok(Some(Frame::Hello { .. })) => {
+                debug!(peer = %peer_id, "忽略重复的Hello");
+            }
+            Ok(None) => break,
+            Err(e) => {
+                debug!(peer = %peer_id, error = %e, "读取失败，关闭连接");
+                break;
+            }
+        }
+    }
+
+    let _ = events
+        .send(ConnectionEvent::Closed {
+            connection_id,
+            peer_id,
+        })
+        .await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::MessageType;
+
+    #[tokio::test]
+    async fn frame_round_trip() {
+        let message = GossipMessage::new(MessageType::Publish)
+            .with_topic("topic".to_string())
+            .with_content(b"hello".to_vec());
+        let mut buffer = Vec::new();
+        write_frame(&mut buffer, &Frame::Message(Box::new(message.clone())))
+            .await
+            .unwrap();
+
+        let mut reader = buffer.as_slice();
+        let Some(Frame::Message(decoded)) = read_frame(&mut reader).await.unwrap() else {
+            panic!("应当读到消息帧");
+        };
+        assert_eq!(decoded.message_id, message.message_id);
+        assert_eq!(decoded.content, message.content);
+        assert!(read_frame(&mut reader).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn read_frame_rejects_oversized_length() {
+        let mut buffer = ((MAX_FRAME_SIZE + 1) as u32).to_be_bytes().to_vec();
+        buffer.extend_from_slice(b"{}");
+
+        let result = read_frame(&mut buffer.as_slice()).await;
+
+        assert!(matches!(result, Err(GossipSubError::Codec(_))));
+    }
+
+    #[tokio::test]
+    async fn write_frame_rejects_oversized_body() {
+        let message = GossipMessage::new(MessageType::Publish).with_content(vec![0; MAX_FRAME_SIZE]);
+        let mut buffer = Vec::new();
+
+        let result = write_frame(&mut buffer, &Frame::Message(Box::new(message))).await;
+
+        assert!(matches!(result, Err(GossipSubError::Codec(_))));
+        assert!(buffer.is_empty());
+    }
+}
@@ -1,25 +1,78 @@
+use crate::error::GossipSubError;
+use crate::message::HistoryQuery;
+use crate::score::{PeerScoreParams, PeerScoreThresholds};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
 // 消息类型枚举
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum MessageType {
     IHave,
     IWant,
     Graft, // 请求加入mesh
     Prune, // 请求离开mesh
     Publish,
+    Subscribe,   // 通知对方我们订阅了主题
+    Unsubscribe, // 通知对方我们取消订阅了主题
+    HistoryRequest,  // 请求主题的历史消息
+    HistoryResponse, // 返回一页历史消息
+}
+
+// 连接方向
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConnectionDirection {
+    Inbound,  // 对方主动连接我们
+    Outbound, // 我们主动连接对方
 }
 
-// GossipSub配置
+// 对等节点连接
 #[derive(Debug, Clone)]
+pub struct PeerConnection {
+    pub connection_info: String,
+    pub direction: ConnectionDirection,
+}
+
+// GossipSub配置，可从配置文件反序列化，缺省字段使用默认值
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct GossipSubConfig {
     pub mesh_size: usize,           // 每个topic的mesh大小
     pub mesh_low: usize,            // mesh最小大小
     pub mesh_high: usize,           // mesh最大大小
+    pub mesh_outbound_min: usize,   // mesh中至少保留的出站连接数
+    pub mesh_retain_score: usize,   // 收缩mesh时按评分保留的节点数
     pub gossip_size: usize,         // gossip消息数量
+    pub d_lazy: usize,              // 每次心跳至少发送IHAVE的节点数
+    pub gossip_factor: f64,         // 发送IHAVE的节点占符合条件节点的比例
     pub heartbeat_interval: u64,    // 心跳间隔(ms)
     pub message_cache_ttl: u64,     // 消息缓存时间(ms)
+    pub seen_ttl: u64,              // 记住已见消息ID的时间(ms)，用于去重
     pub graft_flood_threshold: u64, // GRAFT洪水攻击阈值(ms)
     pub prune_backoff: u64,         // PRUNE后的退避时间(ms)
     pub graft_backoff: u64,         // GRAFT被拒绝后的退避时间(ms)
+    pub unsubscribe_backoff: u64,   // 因取消订阅而PRUNE时的退避时间(ms)
+    pub max_prune_backoff: u64,     // 接受对方PRUNE中退避时间的上限(ms)
+    pub max_transmit_size: usize,   // 单条消息最大传输大小(字节)
+    pub max_ihave_length: usize,    // 单条IHAVE/IWANT最多携带的消息ID数量
+    pub max_ihave_messages: usize,  // 每个心跳周期内从单个peer接受的IHAVE消息数量
+    pub max_iwant_ids: usize,       // 每个心跳周期内向单个peer请求的消息ID总数上限
+    pub gossip_retransmission: u32, // 同一消息通过IWANT重发给同一peer的最大次数
+    pub iwant_followup_time: u64,   // 发送IWANT后等待消息送达的时间(ms)，超时后换其他peer重试
+    pub max_iwant_in_flight: usize, // 同一消息同时向多少个peer发送IWANT
+    pub opportunistic_graft_ticks: u64, // 每隔多少次心跳执行一次机会性GRAFT
+    pub opportunistic_graft_peers: usize, // 每次机会性GRAFT最多加入的节点数
+    pub do_px: bool,                // PRUNE时是否附带节点交换(PX)信息
+    pub prune_peers: usize,         // PRUNE中附带的PX节点数量
+    pub direct_peers: HashMap<String, String>, // 直连节点 peerId -> 连接信息，始终转发、从不加入mesh
+    pub direct_connect_ticks: u64,  // 每隔多少次心跳检查并重连断开的直连节点
+    pub history_sync_messages: usize, // 订阅后向mesh节点同步的历史消息上限，0表示不同步
+    pub history_sync_window: u64,   // 没有本地历史时只同步这段时间内的消息(ms)
+    pub history_page_size: usize,   // 单页历史响应最多携带的消息数
+    pub max_history_requests: usize, // 每个心跳周期内从单个peer接受的历史请求数量
+    pub history_request_timeout: u64, // 等待历史响应的时间(ms)，超时后换其他mesh节点
+    pub rng_seed: Option<u64>,      // 节点选择使用的随机数种子，None时使用系统熵
+    pub score_params: PeerScoreParams, // 节点评分参数
+    pub score_thresholds: PeerScoreThresholds, // 评分阈值
 }
 
 impl Default for GossipSubConfig {
@@ -28,12 +81,499 @@ impl Default for GossipSubConfig {
             mesh_size: 6,
             mesh_low: 4,
             mesh_high: 12,
+            mesh_outbound_min: 2,
+            mesh_retain_score: 4,
             gossip_size: 3,
+            d_lazy: 6,
+            gossip_factor: 0.25,
             heartbeat_interval: 1000,
             message_cache_ttl: 30000,
+            seen_ttl: 120000,             // 2分钟
             graft_flood_threshold: 10000, // 10秒
             prune_backoff: 60000,         // 1分钟
             graft_backoff: 60000,         // 1分钟
+            unsubscribe_backoff: 10000,   // 10秒
+            max_prune_backoff: 3_600_000, // 1小时
+            max_transmit_size: 65536,     // 64KB
+            max_ihave_length: 5000,
+            max_ihave_messages: 10,
+            max_iwant_ids: 5000,
+            gossip_retransmission: 3,
+            iwant_followup_time: 3000,    // 3秒
+            max_iwant_in_flight: 1,
+            opportunistic_graft_ticks: 60,
+            opportunistic_graft_peers: 2,
+            do_px: true,
+            prune_peers: 16,
+            direct_peers: HashMap::new(),
+            direct_connect_ticks: 300,
+            history_sync_messages: 100,
+            history_sync_window: 3_600_000, // 1小时
+            history_page_size: 50,
+            max_history_requests: 10,
+            history_request_timeout: 5000,  // 5秒
+            rng_seed: None,
+            score_params: PeerScoreParams::default(),
+            score_thresholds: PeerScoreThresholds::default(),
         }
     }
 }
+
+impl GossipSubConfig {
+    // 从默认配置开始构建
+    pub fn builder() -> GossipSubConfigBuilder {
+        GossipSubConfigBuilder::new(Self::default())
+    }
+
+    // 低延迟：更大的mesh、更频繁的心跳和更积极的gossip
+    pub fn low_latency() -> Self {
+        Self {
+            mesh_size: 8,
+            mesh_low: 6,
+            mesh_high: 12,
+            mesh_outbound_min: 3,
+            mesh_retain_score: 6,
+            d_lazy: 8,
+            gossip_factor: 0.5,
+            heartbeat_interval: 500,
+            iwant_followup_time: 1000, // 1秒
+            max_iwant_in_flight: 2,
+            ..Self::default()
+        }
+    }
+
+    // 节省带宽：更小的mesh、更少的gossip和更长的心跳间隔
+    pub fn bandwidth_saving() -> Self {
+        Self {
+            mesh_size: 4,
+            mesh_low: 3,
+            mesh_high: 6,
+            mesh_outbound_min: 1,
+            mesh_retain_score: 2,
+            gossip_size: 2,
+            d_lazy: 3,
+            gossip_factor: 0.1,
+            heartbeat_interval: 2000,
+            max_ihave_length: 1000,
+            max_iwant_ids: 1000,
+            gossip_retransmission: 1,
+            prune_peers: 8,
+            ..Self::default()
+        }
+    }
+
+    // 大规模网络：mesh上限更宽松，缓存更久，PX携带更多节点
+    pub fn large_network() -> Self {
+        Self {
+            mesh_size: 8,
+            mesh_low: 6,
+            mesh_high: 16,
+            mesh_outbound_min: 3,
+            mesh_retain_score: 6,
+            gossip_size: 5,
+            d_lazy: 8,
+            message_cache_ttl: 60000, // 1分钟
+            prune_peers: 32,
+            ..Self::default()
+        }
+    }
+
+    // 检查配置参数之间的约束
+    pub fn validate(&self) -> Result<(), GossipSubError> {
+        let invalid = |reason: String| Err(GossipSubError::InvalidConfig(reason));
+
+        if self.mesh_size == 0 {
+            return invalid("mesh_size 必须大于0".to_string());
+        }
+        if !(self.mesh_low <= self.mesh_size && self.mesh_size <= self.mesh_high) {
+            return invalid(format!(
+                "需要 mesh_low <= mesh_size <= mesh_high，实际为 {} / {} / {}",
+                self.mesh_low, self.mesh_size, self.mesh_high
+            ));
+        }
+        // 出站配额不能超过mesh_low，且至少一半的mesh名额留给入站连接
+        if self.mesh_outbound_min > self.mesh_low || self.mesh_outbound_min * 2 > self.mesh_size {
+            return invalid(format!(
+                "mesh_outbound_min ({}) 不能超过 mesh_low ({}) 或 mesh_size ({}) 的一半",
+                self.mesh_outbound_min, self.mesh_low, self.mesh_size
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.gossip_factor) {
+            return invalid(format!(
+                "gossip_factor ({}) 必须在 0.0 到 1.0 之间",
+                self.gossip_factor
+            ));
+        }
+
+        let non_zero = [
+            ("heartbeat_interval", self.heartbeat_interval),
+            ("message_cache_ttl", self.message_cache_ttl),
+            ("seen_ttl", self.seen_ttl),
+            ("iwant_followup_time", self.iwant_followup_time),
+            ("opportunistic_graft_ticks", self.opportunistic_graft_ticks),
+            ("direct_connect_ticks", self.direct_connect_ticks),
+            ("max_transmit_size", self.max_transmit_size as u64),
+            ("max_ihave_length", self.max_ihave_length as u64),
+            ("max_iwant_in_flight", self.max_iwant_in_flight as u64),
+            ("history_page_size", self.history_page_size as u64),
+            ("history_request_timeout", self.history_request_timeout),
+        ];
+        if let Some((name, _)) = non_zero.iter().find(|(_, value)| *value == 0) {
+            return invalid(format!("{} 必须大于0", name));
+        }
+
+        // 评分参数和阈值的顺序
+        let params = &self.score_params;
+        if params.behaviour_penalty_weight > 0.0 {
+            return invalid("behaviour_penalty_weight 不能为正数".to_string());
+        }
+        if !(params.behaviour_penalty_decay > 0.0 && params.behaviour_penalty_decay < 1.0) {
+            return invalid(format!(
+                "behaviour_penalty_decay ({}) 必须在 0.0 到 1.0 之间",
+                params.behaviour_penalty_decay
+            ));
+        }
+        let thresholds = &self.score_thresholds;
+        if !(thresholds.graylist_threshold <= thresholds.publish_threshold
+            && thresholds.publish_threshold <= thresholds.gossip_threshold
+            && thresholds.gossip_threshold <= 0.0)
+        {
+            return invalid(format!(
+                "需要 graylist_threshold <= publish_threshold <= gossip_threshold <= 0，实际为 {} / {} / {}",
+                thresholds.graylist_threshold,
+                thresholds.publish_threshold,
+                thresholds.gossip_threshold
+            ));
+        }
+        if thresholds.accept_px_threshold < 0.0
+            || thresholds.opportunistic_graft_threshold < 0.0
+        {
+            return invalid(
+                "accept_px_threshold 和 opportunistic_graft_threshold 不能为负数".to_string(),
+            );
+        }
+
+        Ok(())
+    }
+}
+
+// GossipSubConfig构建器，build时校验参数
+#[derive(Debug, Clone)]
+pub struct GossipSubConfigBuilder {
+    config: GossipSubConfig,
+}
+
+impl GossipSubConfigBuilder {
+    // 以指定配置（例如预设）为基础构建
+    pub fn new(base: GossipSubConfig) -> Self {
+        Self { config: base }
+    }
+
+    pub fn mesh_size(mut self, mesh_size: usize) -> Self {
+        self.config.mesh_size = mesh_size;
+        self
+    }
+
+    pub fn mesh_low(mut self, mesh_low: usize) -> Self {
+        self.config.mesh_low = mesh_low;
+        self
+    }
+
+    pub fn mesh_high(mut self, mesh_high: usize) -> Self {
+        self.config.mesh_high = mesh_high;
+        self
+    }
+
+    pub fn mesh_outbound_min(mut self, mesh_outbound_min: usize) -> Self {
+        self.config.mesh_outbound_min = mesh_outbound_min;
+        self
+    }
+
+    pub fn mesh_retain_score(mut self, mesh_retain_score: usize) -> Self {
+        self.config.mesh_retain_score = mesh_retain_score;
+        self
+    }
+
+    pub fn gossip_size(mut self, gossip_size: usize) -> Self {
+        self.config.gossip_size = gossip_size;
+        self
+    }
+
+    pub fn d_lazy(mut self, d_lazy: usize) -> Self {
+        self.config.d_lazy = d_lazy;
+        self
+    }
+
+    pub fn gossip_factor(mut self, gossip_factor: f64) -> Self {
+        self.config.gossip_factor = gossip_factor;
+        self
+    }
+
+    pub fn heartbeat_interval(mut self, heartbeat_interval: u64) -> Self {
+        self.config.heartbeat_interval = heartbeat_interval;
+        self
+    }
+
+    pub fn message_cache_ttl(mut self, message_cache_ttl: u64) -> Self {
+        self.config.message_cache_ttl = message_cache_ttl;
+        self
+    }
+
+    pub fn seen_ttl(mut self, seen_ttl: u64) -> Self {
+        self.config.seen_ttl = seen_ttl;
+        self
+    }
+
+    pub fn graft_flood_threshold(mut self, graft_flood_threshold: u64) -> Self {
+        self.config.graft_flood_threshold = graft_flood_threshold;
+        self
+    }
+
+    pub fn prune_backoff(mut self, prune_backoff: u64) -> Self {
+        self.config.prune_backoff = prune_backoff;
+        self
+    }
+
+    pub fn graft_backoff(mut self, graft_backoff: u64) -> Self {
+        self.config.graft_backoff = graft_backoff;
+        self
+    }
+
+    pub fn unsubscribe_backoff(mut self, unsubscribe_backoff: u64) -> Self {
+        self.config.unsubscribe_backoff = unsubscribe_backoff;
+        self
+    }
+
+    pub fn max_transmit_size(mut self, max_transmit_size: usize) -> Self {
+        self.config.max_transmit_size = max_transmit_size;
+        self
+    }
+
+    pub fn max_ihave_length(mut self, max_ihave_length: usize) -> Self {
+        self.config.max_ihave_length = max_ihave_length;
+        self
+    }
+
+    pub fn max_ihave_messages(mut self, max_ihave_messages: usize) -> Self {
+        self.config.max_ihave_messages = max_ihave_messages;
+        self
+    }
+
+    pub fn max_iwant_ids(mut self, max_iwant_ids: usize) -> Self {
+        self.config.max_iwant_ids = max_iwant_ids;
+        self
+    }
+
+    pub fn gossip_retransmission(mut self, gossip_retransmission: u32) -> Self {
+        self.config.gossip_retransmission = gossip_retransmission;
+        self
+    }
+
+    pub fn iwant_followup_time(mut self, iwant_followup_time: u64) -> Self {
+        self.config.iwant_followup_time = iwant_followup_time;
+        self
+    }
+
+    pub fn max_iwant_in_flight(mut self, max_iwant_in_flight: usize) -> Self {
+        self.config.max_iwant_in_flight = max_iwant_in_flight;
+        self
+    }
+
+    pub fn opportunistic_graft_ticks(mut self, opportunistic_graft_ticks: u64) -> Self {
+        self.config.opportunistic_graft_ticks = opportunistic_graft_ticks;
+        self
+    }
+
+    pub fn opportunistic_graft_peers(mut self, opportunistic_graft_peers: usize) -> Self {
+        self.config.opportunistic_graft_peers = opportunistic_graft_peers;
+        self
+    }
+
+    pub fn do_px(mut self, do_px: bool) -> Self {
+        self.config.do_px = do_px;
+        self
+    }
+
+    pub fn prune_peers(mut self, prune_peers: usize) -> Self {
+        self.config.prune_peers = prune_peers;
+        self
+    }
+
+    pub fn direct_peer(mut self, peer_id: String, connection_info: String) -> Self {
+        self.config.direct_peers.insert(peer_id, connection_info);
+        self
+    }
+
+    pub fn direct_connect_ticks(mut self, direct_connect_ticks: u64) -> Self {
+        self.config.direct_connect_ticks = direct_connect_ticks;
+        self
+    }
+
+    pub fn history_sync_messages(mut self, history_sync_messages: usize) -> Self {
+        self.config.history_sync_messages = history_sync_messages;
+        self
+    }
+
+    pub fn history_sync_window(mut self, history_sync_window: u64) -> Self {
+        self.config.history_sync_window = history_sync_window;
+        self
+    }
+
+    pub fn history_page_size(mut self, history_page_size: usize) -> Self {
+        self.config.history_page_size = history_page_size;
+        self
+    }
+
+    pub fn max_history_requests(mut self, max_history_requests: usize) -> Self {
+        self.config.max_history_requests = max_history_requests;
+        self
+    }
+
+    pub fn history_request_timeout(mut self, history_request_timeout: u64) -> Self {
+        self.config.history_request_timeout = history_request_timeout;
+        self
+    }
+
+    pub fn rng_seed(mut self, rng_seed: u64) -> Self {
+        self.config.rng_seed = Some(rng_seed);
+        self
+    }
+
+    pub fn score_params(mut self, score_params: PeerScoreParams) -> Self {
+        self.config.score_params = score_params;
+        self
+    }
+
+    pub fn score_thresholds(mut self, score_thresholds: PeerScoreThresholds) -> Self {
+        self.config.score_thresholds = score_thresholds;
+        self
+    }
+
+    // 校验并返回配置
+    pub fn build(self) -> Result<GossipSubConfig, GossipSubError> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+// 因超出gossip速率限制而被忽略的统计
+#[derive(Debug, Clone, Default)]
+pub struct GossipLimitStats {
+    pub ignored_ihave: u64,           // 超出max_ihave_messages而忽略的IHAVE消息数
+    pub ignored_iwant_ids: u64,       // 超出max_iwant_ids而未请求的消息ID数
+    pub ignored_retransmissions: u64, // 超出gossip_retransmission而未重发的消息数
+    pub ignored_history_requests: u64, // 超出max_history_requests而忽略的历史请求数
+}
+
+// 进行中的IWANT请求
+#[derive(Debug, Clone)]
+pub struct IWantRequest {
+    pub topic: String,
+    pub first_requested: u64,            // 首次请求时间戳
+    pub in_flight: HashMap<String, u64>, // 已发送IWANT的peer -> 请求时间戳
+    pub advertisers: Vec<String>,        // 宣告过该消息、可供重试的peer
+}
+
+// 进行中的历史请求
+#[derive(Debug, Clone)]
+pub struct HistoryRequest {
+    pub peer_id: String,
+    pub topic: String,
+    pub query: HistoryQuery, // 请求的查询，用于校验响应
+    pub sent_at: u64,        // 发送时间戳
+    pub sync: bool,          // 是否属于订阅后的自动同步
+}
+
+// 订阅主题后的历史同步进度
+#[derive(Debug, Clone)]
+pub struct HistorySync {
+    pub query: HistoryQuery,       // 下一页的查询
+    pub received: usize,           // 已收到的消息数
+    pub tried: HashSet<String>,    // 已请求过的peer
+    pub in_flight: Option<String>, // 进行中的请求消息ID
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_are_valid() {
+        for config in [
+            GossipSubConfig::default(),
+            GossipSubConfig::low_latency(),
+            GossipSubConfig::bandwidth_saving(),
+            GossipSubConfig::large_network(),
+        ] {
+            assert_eq!(config.validate(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn validate_rejects_mesh_low_above_mesh_size() {
+        let config = GossipSubConfig {
+            mesh_low: 7,
+            mesh_size: 6,
+            ..GossipSubConfig::default()
+        };
+        assert!(matches!(config.validate(), Err(GossipSubError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn validate_rejects_zero_and_out_of_range_values() {
+        let zero_heartbeat = GossipSubConfig {
+            heartbeat_interval: 0,
+            ..GossipSubConfig::default()
+        };
+        assert!(zero_heartbeat.validate().is_err());
+
+        let gossip_factor = GossipSubConfig {
+            gossip_factor: 1.5,
+            ..GossipSubConfig::default()
+        };
+        assert!(gossip_factor.validate().is_err());
+
+        let outbound = GossipSubConfig {
+            mesh_outbound_min: 4,
+            ..GossipSubConfig::default()
+        };
+        assert!(outbound.validate().is_err());
+    }
+
+    #[test]
+    fn validate_rejects_misordered_score_thresholds() {
+        let misordered = [
+            PeerScoreThresholds {
+                gossip_threshold: 1.0,
+                ..PeerScoreThresholds::default()
+            },
+            PeerScoreThresholds {
+                publish_threshold: -5.0,
+                ..PeerScoreThresholds::default()
+            },
+            PeerScoreThresholds {
+                graylist_threshold: -40.0,
+                ..PeerScoreThresholds::default()
+            },
+        ];
+        for score_thresholds in misordered {
+            let config = GossipSubConfig {
+                score_thresholds,
+                ..GossipSubConfig::default()
+            };
+            assert!(matches!(config.validate(), Err(GossipSubError::InvalidConfig(_))));
+        }
+    }
+
+    #[test]
+    fn builder_validates_on_build() {
+        let config = GossipSubConfig::builder()
+            .mesh_size(8)
+            .mesh_high(12)
+            .build()
+            .unwrap();
+        assert_eq!(config.mesh_size, 8);
+
+        assert!(GossipSubConfig::builder().mesh_high(2).build().is_err());
+    }
+}